@@ -1,95 +1,787 @@
 #![deny(warnings)]
 #![deny(clippy::all)]
 
-use anyhow::Context;
+use std::convert::{TryFrom, TryInto};
+use std::os::unix::fs::PermissionsExt;
+
+use anyhow::{bail, Context};
 use clap::Parser;
+use guest_users_lib::daemon_socket::{Request, Response, DAEMON_SOCKET_PATH};
+use guest_users_lib::logind::{LoginManagerProxy, LoginSessionProxy};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
 use tokio_stream::StreamExt;
 
+/// Interval in which guest users exceeding the retention period get deleted
+const ACCOUNT_RETENTION_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(3600);
+
+/// Interval in which session limits (like the maximum session duration) are enforced
+const SESSION_LIMITS_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Time a client of the daemon socket has to send its request
+const SOCKET_REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Maximum size of a request on the daemon socket
+const SOCKET_REQUEST_MAX_BYTES: u64 = 64 * 1024;
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     #[clap(flatten)]
     log_level: clap_verbosity_flag::Verbosity<clap_verbosity_flag::InfoLevel>,
+
+    /// Validate the configuration and exit
+    #[clap(long, action)]
+    check_config: bool,
+
+    /// Apply the configured network policy and exit
+    #[clap(long, action, conflicts_with = "flush_network_policy")]
+    apply_network_policy: bool,
+
+    /// Remove all guest users network rules and exit
+    #[clap(long, action)]
+    flush_network_policy: bool,
+}
+
+/// Session related events emitted by logind
+enum SessionEvent {
+    New(String, zbus::zvariant::OwnedObjectPath),
+    Removed(String),
+}
+
+/// Cleans up a guest user if it does not have an active session anymore
+async fn cleanup_user(
+    global_settings: &guest_users_lib::helper::Config,
+    system_connection: &zbus::Connection,
+    user: &guest_users_lib::db::models::User,
+) -> anyhow::Result<()> {
+    // check if user has a session
+    if guest_users_lib::helper::has_active_user_sessions_async(
+        global_settings,
+        system_connection,
+        &user.user_name,
+    )
+    .await?
+    {
+        log::info!(
+            "Skipping user {} as the user still has an active session.",
+            &user.user_name
+        );
+        return Ok(());
+    }
+
+    // let logind stop lingering user services first, leftover processes get killed while removing the home directory
+    let login_interface = LoginManagerProxy::new(system_connection).await?;
+    let uid = user.id.try_into()?;
+    if let Err(err) = login_interface.set_user_linger(uid, false, false).await {
+        log::warn!(
+            "Unable to disable lingering of user {}: {err:?}",
+            user.user_name
+        );
+    }
+    // logind does not know about users without processes
+    if let Err(err) = login_interface.terminate_user(uid).await {
+        log::debug!("Unable to terminate user {}: {err:?}", user.user_name);
+    }
+
+    remove_user_files(global_settings, user).await
+}
+
+/// Removes everything a guest user left behind (see `guest_users_lib::helper::cleanup_user`).
+/// Killing processes and removing files blocks, so it runs outside of the runtime to not hold up other tasks.
+async fn remove_user_files(
+    global_settings: &guest_users_lib::helper::Config,
+    user: &guest_users_lib::db::models::User,
+) -> anyhow::Result<()> {
+    let global_settings = global_settings.clone();
+    let user = user.clone();
+    tokio::task::spawn_blocking(move || {
+        guest_users_lib::helper::cleanup_user(&global_settings, &user)
+    })
+    .await??;
+    Ok(())
+}
+
+/// Cleans up a guest user, which might have been left over (e.g. as session events got missed)
+async fn cleanup_leftover_user(
+    global_settings: &guest_users_lib::helper::Config,
+    system_connection: &zbus::Connection,
+    user: &guest_users_lib::db::models::User,
+    current_boot_id: &str,
+) -> anyhow::Result<()> {
+    // sessions of previous boots cannot exist anymore, e.g. after a crash or power loss
+    // (disabled users might still have sessions of the current boot)
+    if user.boot_id != current_boot_id && user.boot_id != guest_users_lib::db::DISABLED_BOOT_ID {
+        log::info!(
+            "User {} got created during a previous boot, cleaning up",
+            user.user_name
+        );
+        remove_user_files(global_settings, user).await
+    } else {
+        cleanup_user(global_settings, system_connection, user).await
+    }
+}
+
+/// Removes the home directories of all guest users not having an active session anymore.
+/// Failing to clean up a single user does not stop cleaning up all others.
+async fn cleanup_users_without_sessions(
+    global_settings: &guest_users_lib::helper::Config,
+    system_connection: &zbus::Connection,
+) -> anyhow::Result<()> {
+    let current_boot_id = guest_users_lib::helper::get_current_os_boot_id()?;
+    let mut db = guest_users_lib::db::DB::new(global_settings)?;
+    for user in db.get_users()? {
+        // check if home directory of user still exists
+        if !std::path::Path::new(&user.home_path).exists() {
+            continue;
+        }
+
+        if let Err(err) =
+            cleanup_leftover_user(global_settings, system_connection, &user, &current_boot_id).await
+        {
+            log::error!("Unable to clean up user {}: {err:?}", user.user_name);
+        }
+    }
+    Ok(())
+}
+
+/// Cleans up a single guest user once its last session ended.
+/// Failing to clean up the user only gets logged, so other users still get cleaned up.
+async fn cleanup_user_without_sessions(
+    global_settings: &guest_users_lib::helper::Config,
+    system_connection: &zbus::Connection,
+    user_name: &str,
+) -> anyhow::Result<()> {
+    let mut db = guest_users_lib::db::DB::new(global_settings)?;
+    if let Some(user) = db.find_user_by_name(user_name)? {
+        if let Err(err) = cleanup_user(global_settings, system_connection, &user).await {
+            log::error!("Unable to clean up user {}: {err:?}", user.user_name);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the name of the user a logind session belongs to
+async fn session_user_name(
+    system_connection: &zbus::Connection,
+    session_path: zbus::zvariant::OwnedObjectPath,
+) -> anyhow::Result<String> {
+    let session = LoginSessionProxy::builder(system_connection)
+        .path(session_path)?
+        .build()
+        .await?;
+    Ok(session.name().await?)
+}
+
+/// Keeps track of logind sessions and cleans up guest users once their last session ended
+async fn session_tracker() -> anyhow::Result<()> {
+    let global_settings = guest_users_lib::helper::get_config()?;
+
+    let system_connection = zbus::Connection::system().await?;
+    let login_interface = LoginManagerProxy::new(&system_connection).await?;
+
+    // subscribe before listing the sessions, so we do not miss any session in between
+    let session_new_events =
+        login_interface
+            .receive_session_new()
+            .await?
+            .filter_map(|msg| match msg.args() {
+                Ok(args) => Some(SessionEvent::New(
+                    args.session_id.to_string(),
+                    args.object_path.into(),
+                )),
+                Err(err) => {
+                    log::warn!("Error parsing session new event: {err:?}");
+                    None
+                }
+            });
+    let session_removed_events =
+        login_interface
+            .receive_session_removed()
+            .await?
+            .filter_map(|msg| match msg.args() {
+                Ok(args) => Some(SessionEvent::Removed(args.session_id.to_string())),
+                Err(err) => {
+                    log::warn!("Error parsing session removed event: {err:?}");
+                    None
+                }
+            });
+    let mut session_events = session_new_events.merge(session_removed_events);
+    log::debug!("Set up receiver for session events!");
+
+    // When receiving the session removed event, we cannot get the metadata of the session anymore.
+    // Therefore, we keep track of the user of every session.
+    let mut session_users: std::collections::HashMap<String, String> = login_interface
+        .list_sessions()
+        .await?
+        .into_iter()
+        .map(|(session_id, _, user_name, _, _)| (session_id, user_name))
+        .collect();
+    log::debug!("Tracking {} existing sessions", session_users.len());
+
+    while let Some(event) = session_events.next().await {
+        match event {
+            SessionEvent::New(session_id, session_path) => {
+                match session_user_name(&system_connection, session_path).await {
+                    Ok(user_name) => {
+                        log::debug!("New session {session_id} of user {user_name}");
+                        session_users.insert(session_id, user_name);
+                    }
+                    // the session might be gone already, the reconcile pass takes care of it
+                    Err(err) => log::warn!("Unable to get user of session {session_id}: {err:?}"),
+                }
+            }
+            SessionEvent::Removed(session_id) => match session_users.remove(&session_id) {
+                Some(user_name) => {
+                    log::debug!("Removed session {session_id} of user {user_name}");
+                    if !session_users.values().any(|other| *other == user_name) {
+                        if let Err(err) = cleanup_user_without_sessions(
+                            &global_settings,
+                            &system_connection,
+                            &user_name,
+                        )
+                        .await
+                        {
+                            log::error!("Unable to clean up user {user_name}: {err:?}");
+                        }
+                    }
+                }
+                None => {
+                    log::info!("Removed unknown session {session_id}, checking all users");
+                    if let Err(err) =
+                        cleanup_users_without_sessions(&global_settings, &system_connection).await
+                    {
+                        log::error!("Unable to clean up users without sessions: {err:?}");
+                    }
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Stores the seat of guest users created before seats got recorded, so they are able to unlock their sessions again.
+/// The seat is taken from their logind sessions, users with sessions on multiple or no seats are left untouched.
+async fn backfill_user_seats(
+    global_settings: &guest_users_lib::helper::Config,
+    system_connection: &zbus::Connection,
+) -> anyhow::Result<()> {
+    let current_boot_id = guest_users_lib::helper::get_current_os_boot_id()?;
+    let login_interface = LoginManagerProxy::new(system_connection).await?;
+    let sessions = login_interface.list_sessions().await?;
+
+    let mut db = guest_users_lib::db::DB::new(global_settings)?;
+    for user in db.get_users()? {
+        if user.seat_id.is_some() || user.boot_id != current_boot_id {
+            continue;
+        }
+        let mut seats: Vec<&str> = sessions
+            .iter()
+            .filter(|(_, _, session_user_name, seat_id, _)| {
+                *session_user_name == user.user_name && !seat_id.is_empty()
+            })
+            .map(|(_, _, _, seat_id, _)| seat_id.as_str())
+            .collect();
+        seats.sort_unstable();
+        seats.dedup();
+        if let [seat_id] = seats[..] {
+            log::info!("Storing seat {seat_id} of user {}", user.user_name);
+            db.backfill_user_seat_id(&user, seat_id)?;
+        }
+    }
+    Ok(())
+}
+
+/// Ends sessions of previous boots, which did not get closed (e.g. due to a power loss), so the session history stays bounded
+fn end_sessions_of_previous_boots(
+    global_settings: &guest_users_lib::helper::Config,
+) -> anyhow::Result<()> {
+    let mut db = guest_users_lib::db::DB::new(global_settings)?;
+    let ended_sessions = db.end_sessions_of_previous_boots(
+        &guest_users_lib::helper::get_current_os_boot_id()?,
+        guest_users_lib::helper::get_current_os_boot_time()?,
+    )?;
+    if ended_sessions > 0 {
+        log::info!("Ended {ended_sessions} session(s) of previous boots, which did not get closed");
+    }
+    Ok(())
+}
+
+/// Runs a single reconcile pass. Failures get logged instead of stopping the daemon,
+/// as a restarted daemon would run into the same failure on startup again.
+async fn reconcile_users(
+    global_settings: &guest_users_lib::helper::Config,
+    system_connection: &zbus::Connection,
+) {
+    if let Err(err) = end_sessions_of_previous_boots(global_settings) {
+        log::error!("Unable to end sessions of previous boots: {err:?}");
+    }
+    if let Err(err) = backfill_user_seats(global_settings, system_connection).await {
+        log::error!("Unable to backfill seats of guest users: {err:?}");
+    }
+    if let Err(err) = cleanup_users_without_sessions(global_settings, system_connection).await {
+        log::error!("Unable to reconcile guest users: {err:?}");
+    }
+}
+
+/// Cleans up all guest users on startup (homes might be left over if the daemon was not running when sessions ended)
+/// and periodically afterwards as fallback for missed session events
+async fn cleanup_reconciler() -> anyhow::Result<()> {
+    let global_settings = guest_users_lib::helper::get_config()?;
+
+    let system_connection = zbus::Connection::system().await?;
+
+    log::info!("Reconciling guest users on startup");
+    reconcile_users(&global_settings, &system_connection).await;
+
+    if global_settings.cleanup_reconcile_interval <= 0 {
+        log::debug!("Periodic reconciliation is disabled");
+        return Ok(());
+    }
+    let reconcile_interval = std::time::Duration::from_secs(
+        u64::try_from(global_settings.cleanup_reconcile_interval)?.saturating_mul(60),
+    );
+
+    // the first pass already happened on startup
+    let mut interval = tokio::time::interval_at(
+        tokio::time::Instant::now() + reconcile_interval,
+        reconcile_interval,
+    );
+    loop {
+        interval.tick().await;
+
+        log::debug!("Reconciling guest users");
+        reconcile_users(&global_settings, &system_connection).await;
+    }
+}
+
+/// Terminates a guest user if it exceeded its maximum session duration
+async fn enforce_user_session_duration(
+    global_settings: &guest_users_lib::helper::Config,
+    system_connection: &zbus::Connection,
+    login_interface: &LoginManagerProxy<'_>,
+    user: &guest_users_lib::db::models::User,
+) -> anyhow::Result<()> {
+    if !guest_users_lib::helper::is_session_deadline_exceeded(global_settings, user)
+        || !guest_users_lib::helper::has_active_user_sessions_async(
+            global_settings,
+            system_connection,
+            &user.user_name,
+        )
+        .await?
+    {
+        return Ok(());
+    }
+
+    log::info!(
+        "User {} exceeded its maximum session duration, terminating it",
+        user.user_name
+    );
+    login_interface.terminate_user(user.id.try_into()?).await?;
+    Ok(())
+}
+
+/// Terminates guest users, which exceeded their maximum session duration
+async fn enforce_session_duration(
+    global_settings: &guest_users_lib::helper::Config,
+    db: &mut guest_users_lib::db::DB<'_>,
+    system_connection: &zbus::Connection,
+    login_interface: &LoginManagerProxy<'_>,
+) -> anyhow::Result<()> {
+    for user in db.get_users()? {
+        // users might log out at any time, so failures only concern a single user
+        if let Err(err) = enforce_user_session_duration(
+            global_settings,
+            system_connection,
+            login_interface,
+            &user,
+        )
+        .await
+        {
+            log::warn!(
+                "Unable to enforce the session duration of user {}: {err:?}",
+                user.user_name
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Terminates a guest session if it is idle for longer than the idle timeout
+async fn enforce_session_idle_timeout(
+    system_connection: &zbus::Connection,
+    login_interface: &LoginManagerProxy<'_>,
+    session_id: &str,
+    session_path: zbus::zvariant::OwnedObjectPath,
+    idle_timeout_usec: u128,
+) -> anyhow::Result<()> {
+    let session = LoginSessionProxy::builder(system_connection)
+        .path(session_path)?
+        .build()
+        .await?;
+    if !session.idle_hint().await? {
+        return Ok(());
+    }
+
+    let now_usec = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_micros();
+    let idle_since_usec = u128::from(session.idle_since_hint().await?);
+    if now_usec.saturating_sub(idle_since_usec) >= idle_timeout_usec {
+        log::info!("Session {session_id} exceeded the idle timeout, terminating it");
+        login_interface.terminate_session(session_id).await?;
+    }
+    Ok(())
+}
+
+/// Terminates guest sessions, which are idle for longer than `guest_idle_timeout`
+async fn enforce_idle_timeout(
+    global_settings: &guest_users_lib::helper::Config,
+    db: &mut guest_users_lib::db::DB<'_>,
+    system_connection: &zbus::Connection,
+    login_interface: &LoginManagerProxy<'_>,
+) -> anyhow::Result<()> {
+    if global_settings.guest_idle_timeout <= 0 {
+        return Ok(());
+    }
+    let idle_timeout_usec = std::time::Duration::from_secs(
+        u64::try_from(global_settings.guest_idle_timeout)?.saturating_mul(60),
+    )
+    .as_micros();
+
+    for (session_id, _, user_name, _, session_path) in login_interface.list_sessions().await? {
+        // never touch sessions of non-guest users
+        if db.find_user_by_name(&user_name)?.is_none() {
+            continue;
+        }
+
+        // sessions might close after listing them, so failures only concern a single session
+        if let Err(err) = enforce_session_idle_timeout(
+            system_connection,
+            login_interface,
+            &session_id,
+            session_path,
+            idle_timeout_usec,
+        )
+        .await
+        {
+            log::warn!(
+                "Unable to enforce the idle timeout of session {session_id} of user {user_name}: {err:?}"
+            );
+        }
+    }
+    Ok(())
 }
 
-#[zbus::proxy(
-    interface = "org.freedesktop.login1.Manager",
-    default_service = "org.freedesktop.login1",
-    default_path = "/org/freedesktop/login1"
-)]
-pub trait LoginManager {
-    /// SessionNew signal
-    #[zbus(signal)]
-    fn session_new(
-        &self,
-        session_id: &str,
-        object_path: zbus::zvariant::ObjectPath<'_>,
-    ) -> zbus::Result<()>;
-
-    /// SessionRemoved signal
-    #[zbus(signal)]
-    fn session_removed(
-        &self,
-        session_id: &str,
-        object_path: zbus::zvariant::ObjectPath<'_>,
-    ) -> zbus::Result<()>;
-}
-
-async fn session_end_listener() -> anyhow::Result<()> {
+/// Periodically logs out guest users, which exceeded their session limits.
+/// Failures get logged and retried on the next check instead of stopping the daemon.
+async fn session_limits_enforcer() -> anyhow::Result<()> {
     let global_settings = guest_users_lib::helper::get_config()?;
 
     let system_connection = zbus::Connection::system().await?;
     let login_interface = LoginManagerProxy::new(&system_connection).await?;
 
-    let mut session_end_events = login_interface.receive_session_removed().await?;
-    log::debug!("Set up receiver for session end events!");
+    let mut interval = tokio::time::interval(SESSION_LIMITS_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
 
-    while let Some(msg) = session_end_events.next().await {
-        let session_removed_args = msg.args().expect("Error parsing message");
+        let mut db = match guest_users_lib::db::DB::new(&global_settings) {
+            Ok(db) => db,
+            Err(err) => {
+                log::error!("Unable to open database to enforce session limits: {err:?}");
+                continue;
+            }
+        };
+        if let Err(err) = enforce_session_duration(
+            &global_settings,
+            &mut db,
+            &system_connection,
+            &login_interface,
+        )
+        .await
+        {
+            log::error!("Unable to enforce the maximum session duration: {err:?}");
+        }
+        if let Err(err) = enforce_idle_timeout(
+            &global_settings,
+            &mut db,
+            &system_connection,
+            &login_interface,
+        )
+        .await
+        {
+            log::error!("Unable to enforce the idle timeout: {err:?}");
+        }
+    }
+}
+
+/// Returns whether a guest user does not own any resources anymore, so it can be safely deleted
+async fn is_guest_user_deletable(
+    global_settings: &guest_users_lib::helper::Config,
+    system_connection: &zbus::Connection,
+    user: &guest_users_lib::db::models::User,
+) -> anyhow::Result<bool> {
+    if std::path::Path::new(&user.home_path).exists() {
+        log::debug!("Home directory of user {} still exists", user.user_name);
+        return Ok(false);
+    }
+    if guest_users_lib::helper::has_active_user_sessions_async(
+        global_settings,
+        system_connection,
+        &user.user_name,
+    )
+    .await?
+    {
+        log::debug!("User {} still has an active session", user.user_name);
+        return Ok(false);
+    }
+    if guest_users_lib::helper::uid_has_processes(user.id.try_into()?)? {
+        log::debug!("User {} still has running processes", user.user_name);
+        return Ok(false);
+    }
+    // scanning the file system blocks, so it runs outside of the runtime to not hold up other tasks
+    let scan_paths = global_settings.guest_account_retention_scan_paths.clone();
+    let uid = user.id.try_into()?;
+    let gid = user.user_group_id.try_into()?;
+    let owned_files = tokio::task::spawn_blocking(move || {
+        guest_users_lib::helper::find_files_owned_by(&scan_paths, Some(uid), Some(gid))
+    })
+    .await??;
+    if let Some(path) = owned_files.first() {
+        log::warn!(
+            "User {} still owns {path:?}, not deleting it",
+            user.user_name
+        );
+        return Ok(false);
+    }
+    Ok(true)
+}
 
-        log::debug!("End session event received: {session_removed_args:?}");
+/// Deletes a guest user if it exceeded the retention period and does not own any resources anymore
+async fn enforce_user_retention(
+    global_settings: &guest_users_lib::helper::Config,
+    system_connection: &zbus::Connection,
+    db: &mut guest_users_lib::db::DB<'_>,
+    user: &guest_users_lib::db::models::User,
+) -> anyhow::Result<()> {
+    if guest_users_lib::helper::is_retention_period_exceeded(global_settings, user)
+        && is_guest_user_deletable(global_settings, system_connection, user).await?
+    {
+        log::info!(
+            "User {} exceeded the retention period, deleting it",
+            user.user_name
+        );
+        db.delete_guest_user(user)?;
+    }
+    Ok(())
+}
 
-        // Unfortunately, when receiving the session end event, we cannot get the metadata of the session anymore.
-        // Therefore, we are left with two options:
-        // 1. Store the metadata beforehand (but then we do have data duplicated)
-        // 2. Look up which sessions are running after receiving an session end event and just try to clean up all users, which do not have a session
-        // We chose the second option to prevent data duplication (and therefore nasty bugs).
+/// Deletes all guest users, which exceeded the retention period.
+/// Failing to delete a single user does not stop deleting all others.
+async fn enforce_account_retention(
+    global_settings: &guest_users_lib::helper::Config,
+    system_connection: &zbus::Connection,
+) -> anyhow::Result<()> {
+    let mut db = guest_users_lib::db::DB::new(global_settings)?;
+    for user in db.get_users()? {
+        if let Err(err) =
+            enforce_user_retention(global_settings, system_connection, &mut db, &user).await
+        {
+            log::error!(
+                "Unable to enforce the retention period of user {}: {err:?}",
+                user.user_name
+            );
+        }
+    }
+    Ok(())
+}
 
+/// Scans retired IDs for leftovers now, so creating a guest user during login does not have to.
+/// Scanning the file system blocks, so it runs outside of the runtime to not hold up other tasks.
+async fn prepare_retired_ids(
+    global_settings: &guest_users_lib::helper::Config,
+) -> anyhow::Result<()> {
+    let global_settings = global_settings.clone();
+    tokio::task::spawn_blocking(move || {
         let mut db = guest_users_lib::db::DB::new(&global_settings)?;
-        for user in db.get_users()? {
-            // check if home directory of user still exists
-            let home_path = std::path::Path::new(&user.home_path);
-            if home_path.exists() {
-                // failsafe: check whether this path is in home base directory
-                let home_base_path = std::path::Path::new(&global_settings.home_base_path);
-                if !home_path.starts_with(home_base_path) {
-                    log::warn!("{home_path:?} not in home_base_path={home_base_path:?}, skipping deletion!")
-                } else {
-                    // check if user has a session
-                    if !guest_users_lib::helper::has_active_user_sessions(&user.user_name)? {
-                        log::info!(
-                            "Removing home directory {home_path:?} of user {}",
-                            user.user_name
-                        );
-                        std::fs::remove_dir_all(home_path).with_context(|| {
-                            format!("Removing home directory of {} failed!", user.user_name)
-                        })?;
-                    } else {
-                        log::info!(
-                            "Skipping user {} as the user still has an active session.",
-                            &user.user_name
-                        );
-                    }
-                }
+        db.prepare_retired_ids()
+    })
+    .await?
+}
+
+/// Periodically deletes guest users, which exceeded the retention period, and prepares their IDs for recycling.
+/// Failures get logged and retried on the next check instead of stopping the daemon.
+async fn account_retention_enforcer() -> anyhow::Result<()> {
+    let global_settings = guest_users_lib::helper::get_config()?;
+    if global_settings.guest_account_retention_days <= 0 {
+        log::debug!("Guest account retention is disabled");
+        return Ok(());
+    }
+
+    let system_connection = zbus::Connection::system().await?;
+
+    let mut interval = tokio::time::interval(ACCOUNT_RETENTION_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = enforce_account_retention(&global_settings, &system_connection).await {
+            log::error!("Unable to enforce account retention: {err:?}");
+        }
+        if let Err(err) = prepare_retired_ids(&global_settings).await {
+            log::error!("Unable to prepare retired IDs for recycling: {err:?}");
+        }
+    }
+}
+
+/// Handles a request of the PAM module sent by a process running as `peer_uid`.
+/// Returns the response together with a guest user, which needs to be cleaned up once the response got sent.
+async fn handle_socket_request(
+    global_settings: &guest_users_lib::helper::Config,
+    request: Request,
+    peer_uid: u32,
+) -> anyhow::Result<(Response, Option<guest_users_lib::db::models::User>)> {
+    let mut db = guest_users_lib::db::DB::new(global_settings)?;
+    match request {
+        Request::OpenSession {
+            user_name,
+            service,
+            tty,
+            rhost,
+            seat_id,
+        } => {
+            let user = db
+                .find_user_by_name(&user_name)?
+                .with_context(|| format!("Unknown guest user {user_name}"))?;
+            // non-root processes may only record sessions of themselves
+            if peer_uid != 0 && i64::from(peer_uid) != user.id {
+                bail!("UID {peer_uid} is not allowed to open a session of user {user_name}");
+            }
+            let session = db.start_session(&user, service, tty, rhost, seat_id)?;
+            log::info!("Opened session {} of user {user_name}", session.id);
+            Ok((
+                Response::SessionOpened {
+                    session_id: session.id,
+                },
+                None,
+            ))
+        }
+        Request::CloseSession { session_id } => {
+            let session = db
+                .find_session_by_id(session_id)?
+                .with_context(|| format!("Unknown session {session_id}"))?;
+            if peer_uid != 0 && i64::from(peer_uid) != session.user_id {
+                bail!("UID {peer_uid} is not allowed to close session {session_id}");
+            }
+            // root processes (like display managers) record the end of the session themselves
+            if db.end_session(session_id)?.is_some() {
+                log::info!("Closed session {session_id} of user {}", session.user_name);
+            }
+
+            let user = match db.find_user_by_id(session.user_id.try_into()?)? {
+                Some(user) if user.user_name == session.user_name => user,
+                _ => return Ok((Response::SessionClosed, None)),
+            };
+            if !db.find_open_sessions_for_user(&user)?.is_empty() {
+                log::debug!(
+                    "User {} still has open sessions, skipping cleanup",
+                    user.user_name
+                );
+                return Ok((Response::SessionClosed, None));
+            }
+            Ok((Response::SessionClosed, Some(user)))
+        }
+    }
+}
+
+/// Handles a single connection to the daemon socket carrying one request
+async fn handle_socket_connection(
+    global_settings: &guest_users_lib::helper::Config,
+    system_connection: &zbus::Connection,
+    stream: tokio::net::UnixStream,
+) -> anyhow::Result<()> {
+    let peer_uid = stream.peer_cred()?.uid();
+    let (reader, mut writer) = stream.into_split();
+
+    let mut line = String::new();
+    tokio::time::timeout(
+        SOCKET_REQUEST_TIMEOUT,
+        tokio::io::BufReader::new(reader.take(SOCKET_REQUEST_MAX_BYTES)).read_line(&mut line),
+    )
+    .await
+    .context("Timed out waiting for request")??;
+
+    let request = serde_json::from_str::<Request>(&line)
+        .with_context(|| format!("Invalid request of UID {peer_uid}"));
+    let (response, cleanup) = match request {
+        Ok(request) => match handle_socket_request(global_settings, request, peer_uid).await {
+            Ok(result) => result,
+            Err(err) => {
+                log::warn!("Unable to handle request of UID {peer_uid}: {err:?}");
+                (Response::Error(format!("{err:#}")), None)
             }
+        },
+        Err(err) => (Response::Error(format!("{err:#}")), None),
+    };
+
+    let mut response = serde_json::to_string(&response)?;
+    response.push('\n');
+    writer.write_all(response.as_bytes()).await?;
+    drop(writer);
+
+    // clean up after responding, so the closing session does not have to wait for it
+    if let Some(user) = cleanup {
+        if let Err(err) = cleanup_user(global_settings, system_connection, &user).await {
+            log::error!("Unable to clean up user {}: {err:?}", user.user_name);
         }
     }
     Ok(())
 }
 
+/// Listens for requests of the PAM module, e.g. to clean up guest users once their last session got closed
+async fn session_socket_server() -> anyhow::Result<()> {
+    let global_settings = std::sync::Arc::new(guest_users_lib::helper::get_config()?);
+
+    let system_connection = zbus::Connection::system().await?;
+
+    let socket_path = std::path::Path::new(DAEMON_SOCKET_PATH);
+    if let Some(socket_directory) = socket_path.parent() {
+        std::fs::create_dir_all(socket_directory)?;
+    }
+    // the socket of a previous run is left over if the daemon did not exit cleanly
+    if let Err(err) = std::fs::remove_file(socket_path) {
+        if err.kind() != std::io::ErrorKind::NotFound {
+            return Err(err).with_context(|| format!("Unable to remove {DAEMON_SOCKET_PATH}"));
+        }
+    }
+    let listener = tokio::net::UnixListener::bind(socket_path)
+        .with_context(|| format!("Unable to listen on {DAEMON_SOCKET_PATH}"))?;
+    // every process may connect, requests get authorized by the UID of the connecting process
+    std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(0o666))?;
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                log::warn!("Unable to accept connection on {DAEMON_SOCKET_PATH}: {err:?}");
+                continue;
+            }
+        };
+        // handle connections concurrently, so a slow client or cleanup does not hold up other sessions
+        let global_settings = global_settings.clone();
+        let system_connection = system_connection.clone();
+        tokio::spawn(async move {
+            if let Err(err) =
+                handle_socket_connection(&global_settings, &system_connection, stream).await
+            {
+                log::warn!("Unable to handle connection on {DAEMON_SOCKET_PATH}: {err:?}");
+            }
+        });
+    }
+}
+
 fn main() -> anyhow::Result<()> {
     tokio::runtime::Builder::new_current_thread()
         .enable_io()
+        .enable_time()
         .build()
         .unwrap()
         .block_on(main_async())
@@ -104,7 +796,26 @@ async fn main_async() -> anyhow::Result<()> {
         .init()
         .unwrap();
 
-    session_end_listener().await?;
+    // refuse to start with an invalid configuration instead of failing within the tasks
+    let global_settings = guest_users_lib::helper::get_config()?;
+    if args.check_config {
+        println!("Configuration is valid");
+        return Ok(());
+    }
+    if args.apply_network_policy {
+        return guest_users_lib::network_policy::apply(&global_settings);
+    }
+    if args.flush_network_policy {
+        return guest_users_lib::network_policy::flush();
+    }
+
+    tokio::try_join!(
+        session_tracker(),
+        cleanup_reconciler(),
+        session_limits_enforcer(),
+        account_retention_enforcer(),
+        session_socket_server()
+    )?;
 
     Ok(())
 }
@@ -0,0 +1,482 @@
+#![deny(warnings)]
+#![deny(clippy::all)]
+
+use std::convert::TryInto;
+
+use clap::{Parser, Subcommand};
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    #[clap(flatten)]
+    log_level: clap_verbosity_flag::Verbosity,
+
+    /// Print output as JSON instead of a human readable table
+    #[clap(long, global = true, action)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    #[command(flatten)]
+    Database(DatabaseCommand),
+    /// Validate a configuration file before deploying it
+    CheckConfig {
+        /// Path of the configuration file
+        #[clap(default_value = guest_users_lib::helper::CONFIG_FILE_PATH)]
+        path: std::path::PathBuf,
+    },
+    /// Manage the nftables network policy for guest users
+    NetworkPolicy {
+        #[command(subcommand)]
+        action: NetworkPolicyAction,
+    },
+}
+
+/// Commands working on the guest users database
+#[derive(Subcommand, Debug)]
+enum DatabaseCommand {
+    /// List all guest users
+    List,
+    /// Show details of a single guest user
+    Show { user: String },
+    /// List all guest users having an active session
+    Sessions,
+    /// Remove the home directory of a guest user without an active session
+    PurgeHome { user: String },
+    /// Disable a guest user, so it cannot (re-)login anymore
+    Disable { user: String },
+    /// List all recycled user and group IDs
+    Recycles,
+    /// List recorded guest sessions (times are shown in UTC)
+    History {
+        /// Only list sessions open at this local time, e.g. "2024-05-17 14:32" or RFC 3339
+        #[clap(long)]
+        at: Option<String>,
+        /// Only list sessions on this seat, e.g. "seat0"
+        #[clap(long)]
+        seat: Option<String>,
+        /// Only list sessions of this guest user
+        #[clap(long)]
+        user: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum NetworkPolicyAction {
+    /// Print the nftables ruleset generated from the configuration
+    Show,
+    /// Apply the configured network policy
+    Apply,
+    /// Remove all guest users network rules
+    Flush,
+}
+
+#[derive(serde::Serialize, Debug)]
+struct SessionInfo {
+    id: i64,
+    user_name: String,
+    uid: i64,
+    boot_id: String,
+    started_at: String,
+    ended_at: Option<String>,
+    /// The session did not get closed during its boot, so it ended before `ended_at` (the start of the next boot)
+    end_unknown: bool,
+    service: Option<String>,
+    tty: Option<String>,
+    rhost: Option<String>,
+    seat_id: Option<String>,
+}
+
+impl SessionInfo {
+    fn table_header() -> Vec<&'static str> {
+        vec![
+            "ID",
+            "USER",
+            "UID",
+            "STARTED AT",
+            "ENDED AT",
+            "SERVICE",
+            "TTY",
+            "RHOST",
+            "SEAT",
+        ]
+    }
+
+    fn table_row(self) -> Vec<String> {
+        let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        vec![
+            self.id.to_string(),
+            self.user_name,
+            self.uid.to_string(),
+            self.started_at,
+            match self.ended_at {
+                Some(ended_at) if self.end_unknown => format!("before {ended_at}"),
+                ended_at => or_dash(ended_at),
+            },
+            or_dash(self.service),
+            or_dash(self.tty),
+            or_dash(self.rhost),
+            or_dash(self.seat_id),
+        ]
+    }
+}
+
+/// Parses a local time given on the command line and converts it to UTC
+fn parse_local_time(time: &str) -> anyhow::Result<chrono::NaiveDateTime> {
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(time) {
+        return Ok(time.naive_utc());
+    }
+    let local_time = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| chrono::NaiveDateTime::parse_from_str(time, format).ok())
+        .ok_or_else(|| {
+            anyhow::anyhow!("Invalid time '{time}', expected e.g. \"2024-05-17 14:32\" or RFC 3339")
+        })?;
+    Ok(local_time
+        .and_local_timezone(chrono::Local)
+        .earliest()
+        .ok_or_else(|| anyhow::anyhow!("Time '{time}' does not exist in the local timezone"))?
+        .naive_utc())
+}
+
+#[derive(serde::Serialize, Debug)]
+struct IdRecycleInfo {
+    id_type: String,
+    recycled_id: i64,
+    retired_at: String,
+    recycled_at: String,
+    leftover_count: i64,
+    leftover_policy: String,
+}
+
+#[derive(serde::Serialize, Debug)]
+struct UserInfo {
+    user_name: String,
+    uid: i64,
+    gid: i64,
+    group_name: Option<String>,
+    supplementary_group_ids: Vec<i64>,
+    home_path: String,
+    home_exists: bool,
+    boot_id: String,
+    created_at: String,
+    seat_id: Option<String>,
+    current_boot: bool,
+    disabled: bool,
+    active_session: bool,
+}
+
+impl UserInfo {
+    fn table_header() -> Vec<&'static str> {
+        vec![
+            "USER",
+            "UID",
+            "GID",
+            "HOME",
+            "CURRENT BOOT",
+            "DISABLED",
+            "ACTIVE",
+        ]
+    }
+
+    fn table_row(&self) -> Vec<String> {
+        vec![
+            self.user_name.clone(),
+            self.uid.to_string(),
+            self.gid.to_string(),
+            if self.home_exists {
+                self.home_path.clone()
+            } else {
+                "-".to_string()
+            },
+            yes_no(self.current_boot),
+            yes_no(self.disabled),
+            yes_no(self.active_session),
+        ]
+    }
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+fn user_info(
+    global_settings: &guest_users_lib::helper::Config,
+    db: &mut guest_users_lib::db::DB,
+    user: &guest_users_lib::db::models::User,
+    current_boot_id: &str,
+) -> anyhow::Result<UserInfo> {
+    let group = db.find_group_by_id(user.user_group_id.try_into()?)?;
+    let supplementary_group_ids = db
+        .find_groups_for_user(user)?
+        .iter()
+        .map(|membership| membership.group_id)
+        .collect();
+
+    Ok(UserInfo {
+        user_name: user.user_name.clone(),
+        uid: user.id,
+        gid: user.user_group_id,
+        group_name: group.map(|group| group.group_name),
+        supplementary_group_ids,
+        home_path: user.home_path.clone(),
+        home_exists: std::path::Path::new(&user.home_path).exists(),
+        boot_id: user.boot_id.clone(),
+        created_at: user.created_at.to_string(),
+        seat_id: user.seat_id.clone(),
+        current_boot: user.boot_id == current_boot_id,
+        disabled: user.boot_id == guest_users_lib::db::DISABLED_BOOT_ID,
+        active_session: guest_users_lib::helper::has_active_user_sessions(
+            global_settings,
+            &user.user_name,
+        )?,
+    })
+}
+
+fn find_user(
+    db: &mut guest_users_lib::db::DB,
+    user_name: &str,
+) -> anyhow::Result<guest_users_lib::db::models::User> {
+    db.find_user_by_name(user_name)?
+        .ok_or_else(|| anyhow::anyhow!("Guest user {user_name} does not exist!"))
+}
+
+fn print_table(header: Vec<&str>, rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = header.iter().map(|column| column.len()).collect();
+    for row in rows.iter() {
+        for (width, column) in widths.iter_mut().zip(row.iter()) {
+            *width = std::cmp::max(*width, column.len());
+        }
+    }
+
+    let print_row = |row: Vec<String>| {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(column, width)| format!("{column:<width$}"))
+            .collect::<Vec<String>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    };
+
+    print_row(header.iter().map(|column| column.to_string()).collect());
+    for row in rows {
+        print_row(row);
+    }
+}
+
+fn print_users(users: &[UserInfo], json: bool) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(users)?);
+    } else {
+        print_table(
+            UserInfo::table_header(),
+            users.iter().map(|user| user.table_row()).collect(),
+        );
+    }
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    simple_logger::SimpleLogger::new()
+        .with_level(args.log_level.log_level().unwrap().to_level_filter())
+        .with_utc_timestamps()
+        .init()
+        .unwrap();
+
+    let command = match args.command {
+        // checking the configuration must not depend on the deployed configuration being valid
+        Command::CheckConfig { path } => {
+            guest_users_lib::helper::load_config(&path)?;
+            println!("Configuration {} is valid", path.display());
+            return Ok(());
+        }
+        // the network policy does not depend on any guest user, so it works without the database
+        Command::NetworkPolicy { action } => {
+            let global_settings = guest_users_lib::helper::get_config()?;
+            match action {
+                NetworkPolicyAction::Show => {
+                    print!(
+                        "{}",
+                        guest_users_lib::network_policy::generate_ruleset(&global_settings)?
+                    );
+                }
+                NetworkPolicyAction::Apply => {
+                    guest_users_lib::network_policy::apply(&global_settings)?;
+                }
+                NetworkPolicyAction::Flush => guest_users_lib::network_policy::flush()?,
+            }
+            return Ok(());
+        }
+        Command::Database(command) => command,
+    };
+
+    let global_settings = guest_users_lib::helper::get_config()?;
+    let mut db = guest_users_lib::db::DB::new(&global_settings)?;
+    let current_boot_id = guest_users_lib::helper::get_current_os_boot_id()?;
+
+    match command {
+        DatabaseCommand::List => {
+            let mut users = Vec::new();
+            for user in db.get_users()? {
+                users.push(user_info(
+                    &global_settings,
+                    &mut db,
+                    &user,
+                    &current_boot_id,
+                )?);
+            }
+            print_users(&users, args.json)?;
+        }
+        DatabaseCommand::Show { user } => {
+            let user = find_user(&mut db, &user)?;
+            let info = user_info(&global_settings, &mut db, &user, &current_boot_id)?;
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&info)?);
+            } else {
+                print_table(
+                    vec!["KEY", "VALUE"],
+                    vec![
+                        vec!["user_name".to_string(), info.user_name],
+                        vec!["uid".to_string(), info.uid.to_string()],
+                        vec!["gid".to_string(), info.gid.to_string()],
+                        vec![
+                            "group_name".to_string(),
+                            info.group_name.unwrap_or_else(|| "-".to_string()),
+                        ],
+                        vec![
+                            "supplementary_group_ids".to_string(),
+                            info.supplementary_group_ids
+                                .iter()
+                                .map(|gid| gid.to_string())
+                                .collect::<Vec<String>>()
+                                .join(","),
+                        ],
+                        vec!["home_path".to_string(), info.home_path],
+                        vec!["home_exists".to_string(), yes_no(info.home_exists)],
+                        vec!["boot_id".to_string(), info.boot_id],
+                        vec!["created_at".to_string(), info.created_at],
+                        vec![
+                            "seat_id".to_string(),
+                            info.seat_id.unwrap_or_else(|| "-".to_string()),
+                        ],
+                        vec!["current_boot".to_string(), yes_no(info.current_boot)],
+                        vec!["disabled".to_string(), yes_no(info.disabled)],
+                        vec!["active_session".to_string(), yes_no(info.active_session)],
+                    ],
+                );
+            }
+        }
+        DatabaseCommand::Sessions => {
+            let mut users = Vec::new();
+            for user in db.get_users()? {
+                let info = user_info(&global_settings, &mut db, &user, &current_boot_id)?;
+                if info.active_session {
+                    users.push(info);
+                }
+            }
+            print_users(&users, args.json)?;
+        }
+        DatabaseCommand::PurgeHome { user } => {
+            let user = find_user(&mut db, &user)?;
+            // never pull the home directory from under a running session
+            if guest_users_lib::helper::has_active_user_sessions(&global_settings, &user.user_name)?
+            {
+                anyhow::bail!(
+                    "User {} still has an active session, refusing to purge its home directory!",
+                    user.user_name
+                );
+            }
+            if guest_users_lib::helper::cleanup_user_home(&global_settings, &user)? {
+                println!("Purged home directory of user {}", user.user_name);
+            } else {
+                println!("Home directory of user {} not purged", user.user_name);
+            }
+        }
+        DatabaseCommand::Disable { user } => {
+            let user = find_user(&mut db, &user)?;
+            db.disable_user(&user)?;
+            println!("Disabled user {}", user.user_name);
+        }
+        DatabaseCommand::Recycles => {
+            let id_recycles: Vec<IdRecycleInfo> = db
+                .get_id_recycles()?
+                .into_iter()
+                .map(|id_recycle| IdRecycleInfo {
+                    id_type: id_recycle.id_type,
+                    recycled_id: id_recycle.recycled_id,
+                    retired_at: id_recycle.retired_at.to_string(),
+                    recycled_at: id_recycle.recycled_at.to_string(),
+                    leftover_count: id_recycle.leftover_count,
+                    leftover_policy: id_recycle.leftover_policy,
+                })
+                .collect();
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&id_recycles)?);
+            } else {
+                print_table(
+                    vec![
+                        "TYPE",
+                        "ID",
+                        "RETIRED AT",
+                        "RECYCLED AT",
+                        "LEFTOVERS",
+                        "POLICY",
+                    ],
+                    id_recycles
+                        .into_iter()
+                        .map(|id_recycle| {
+                            vec![
+                                id_recycle.id_type,
+                                id_recycle.recycled_id.to_string(),
+                                id_recycle.retired_at,
+                                id_recycle.recycled_at,
+                                id_recycle.leftover_count.to_string(),
+                                id_recycle.leftover_policy,
+                            ]
+                        })
+                        .collect(),
+                );
+            }
+        }
+        DatabaseCommand::History { at, seat, user } => {
+            let at = at.map(|at| parse_local_time(&at)).transpose()?;
+            let sessions: Vec<SessionInfo> = db
+                .find_sessions(at, seat.as_deref(), user.as_deref())?
+                .into_iter()
+                .map(|session| SessionInfo {
+                    id: session.id,
+                    user_name: session.user_name,
+                    uid: session.user_id,
+                    boot_id: session.boot_id,
+                    started_at: session.started_at.to_string(),
+                    ended_at: session.ended_at.map(|ended_at| ended_at.to_string()),
+                    end_unknown: session.end_unknown,
+                    service: session.service,
+                    tty: session.tty,
+                    rhost: session.rhost,
+                    seat_id: session.seat_id,
+                })
+                .collect();
+            if args.json {
+                println!("{}", serde_json::to_string_pretty(&sessions)?);
+            } else {
+                print_table(
+                    SessionInfo::table_header(),
+                    sessions
+                        .into_iter()
+                        .map(|session| session.table_row())
+                        .collect(),
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,14 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE user_group_memberships_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    group_id BIGINT NOT NULL CONSTRAINT fk_user_group_memberships_group REFERENCES groups(id),
+    user_id BIGINT NOT NULL CONSTRAINT fk_user_group_memberships_user REFERENCES users(id)
+);
+
+-- memberships of system groups cannot be represented anymore
+INSERT INTO user_group_memberships_new SELECT id, group_id, user_id FROM user_group_memberships WHERE group_id IN (SELECT id FROM groups);
+DROP TABLE user_group_memberships;
+ALTER TABLE user_group_memberships_new RENAME TO user_group_memberships;
+
+CREATE INDEX group_group_memberships_group_id_idx ON user_group_memberships(group_id);
+CREATE INDEX group_group_memberships_user_id_idx ON user_group_memberships(user_id);
@@ -0,0 +1,14 @@
+-- Supplementary group memberships reference system groups (e.g. audio, video), which are not part of the groups table.
+-- Therefore, drop the foreign key constraint on group_id.
+CREATE TABLE user_group_memberships_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    group_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL CONSTRAINT fk_user_group_memberships_user REFERENCES users(id)
+);
+
+INSERT INTO user_group_memberships_new SELECT id, group_id, user_id FROM user_group_memberships;
+DROP TABLE user_group_memberships;
+ALTER TABLE user_group_memberships_new RENAME TO user_group_memberships;
+
+CREATE INDEX group_group_memberships_group_id_idx ON user_group_memberships(group_id);
+CREATE INDEX group_group_memberships_user_id_idx ON user_group_memberships(user_id);
@@ -0,0 +1,3 @@
+-- SQLite does not allow adding columns with a non-constant default, so existing users are treated as created right now
+ALTER TABLE users ADD COLUMN created_at TIMESTAMP NOT NULL DEFAULT '1970-01-01 00:00:00';
+UPDATE users SET created_at = datetime('now');
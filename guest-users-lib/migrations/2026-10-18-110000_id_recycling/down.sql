@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE retired_ids;
+DROP TABLE id_recycles;
@@ -0,0 +1,21 @@
+-- IDs of deleted guest users, which might get recycled once all IDs got assigned
+CREATE TABLE retired_ids (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    id_type TEXT NOT NULL,
+    retired_id BIGINT NOT NULL,
+    retired_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX retired_ids_id_type_retired_at_idx ON retired_ids(id_type, retired_at);
+
+
+-- audit log of recycled IDs
+CREATE TABLE id_recycles (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    id_type TEXT NOT NULL,
+    recycled_id BIGINT NOT NULL,
+    retired_at TIMESTAMP NOT NULL,
+    recycled_at TIMESTAMP NOT NULL,
+    leftover_count BIGINT NOT NULL,
+    leftover_policy TEXT NOT NULL
+);
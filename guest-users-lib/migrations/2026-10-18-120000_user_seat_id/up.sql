@@ -0,0 +1,2 @@
+-- logind seat a guest user got created on, NULL for guest users created without a seat (e.g. remote sessions)
+ALTER TABLE users ADD COLUMN seat_id TEXT;
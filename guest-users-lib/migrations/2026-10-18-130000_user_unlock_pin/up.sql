@@ -0,0 +1,2 @@
+-- hash of the PIN a guest user needs to enter on re-login, NULL if no PIN is set
+ALTER TABLE users ADD COLUMN unlock_pin_hash TEXT;
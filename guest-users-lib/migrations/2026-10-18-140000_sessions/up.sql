@@ -0,0 +1,12 @@
+-- guest user sessions as recorded by the PAM module
+-- users might get deleted (and their IDs recycled), so sessions keep the user name instead of referencing the user
+CREATE TABLE sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    user_id BIGINT NOT NULL,
+    user_name TEXT NOT NULL,
+    boot_id TEXT NOT NULL,
+    started_at TIMESTAMP NOT NULL,
+    ended_at TIMESTAMP
+);
+
+CREATE INDEX sessions_user_id_ended_at_idx ON sessions(user_id, ended_at);
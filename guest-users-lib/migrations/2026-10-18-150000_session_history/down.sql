@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX sessions_started_at_idx;
+
+ALTER TABLE sessions DROP COLUMN seat_id;
+ALTER TABLE sessions DROP COLUMN rhost;
+ALTER TABLE sessions DROP COLUMN tty;
+ALTER TABLE sessions DROP COLUMN service;
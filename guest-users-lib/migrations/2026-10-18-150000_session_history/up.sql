@@ -0,0 +1,7 @@
+-- origin of guest user sessions for auditing
+ALTER TABLE sessions ADD COLUMN service TEXT;
+ALTER TABLE sessions ADD COLUMN tty TEXT;
+ALTER TABLE sessions ADD COLUMN rhost TEXT;
+ALTER TABLE sessions ADD COLUMN seat_id TEXT;
+
+CREATE INDEX sessions_started_at_idx ON sessions(started_at);
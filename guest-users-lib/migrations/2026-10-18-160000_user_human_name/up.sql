@@ -0,0 +1,2 @@
+-- human readable name (e.g. shown on login screens), NULL for users created before names got stored
+ALTER TABLE users ADD COLUMN human_name TEXT;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE retired_ids DROP COLUMN leftover_policy;
+ALTER TABLE retired_ids DROP COLUMN leftover_count;
+ALTER TABLE retired_ids DROP COLUMN prepared_at;
@@ -0,0 +1,4 @@
+-- retired IDs get scanned for leftovers by the cleanup daemon ahead of time, NULL until they got prepared for recycling
+ALTER TABLE retired_ids ADD COLUMN prepared_at TIMESTAMP;
+ALTER TABLE retired_ids ADD COLUMN leftover_count BIGINT;
+ALTER TABLE retired_ids ADD COLUMN leftover_policy TEXT;
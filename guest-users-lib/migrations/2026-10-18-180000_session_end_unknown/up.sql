@@ -0,0 +1,2 @@
+-- sessions of previous boots, which never got closed (e.g. due to a power loss), get bounded by the start of the next boot
+ALTER TABLE sessions ADD COLUMN end_unknown BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,57 @@
+//! Requests sent by the PAM module to the cleanup daemon via a Unix socket.
+//! Each connection carries a single request and a single response, both encoded as one line of JSON.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+
+use anyhow::Context;
+
+/// Socket the cleanup daemon listens on
+pub const DAEMON_SOCKET_PATH: &str = "/run/guest-users/cleanup-daemon.sock";
+
+/// Time a client waits for the cleanup daemon to respond
+const CLIENT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum Request {
+    /// Records the start of a session of a guest user, sent if the PAM module cannot write to the database itself
+    OpenSession {
+        user_name: String,
+        service: Option<String>,
+        tty: Option<String>,
+        rhost: Option<String>,
+        seat_id: Option<String>,
+    },
+    /// Records the end of a session (if not done already) and cleans up the guest user once its last session ended
+    CloseSession { session_id: i64 },
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum Response {
+    SessionOpened { session_id: i64 },
+    SessionClosed,
+    Error(String),
+}
+
+/// Sends a request to the cleanup daemon and waits for its response
+pub fn send_request(request: &Request) -> anyhow::Result<Response> {
+    let mut stream = UnixStream::connect(DAEMON_SOCKET_PATH)
+        .with_context(|| format!("Unable to connect to cleanup daemon at {DAEMON_SOCKET_PATH}"))?;
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+
+    let mut response = String::new();
+    BufReader::new(stream)
+        .read_line(&mut response)
+        .context("Unable to read response of cleanup daemon")?;
+    match serde_json::from_str(&response)
+        .with_context(|| format!("Invalid response of cleanup daemon '{}'", response.trim()))?
+    {
+        Response::Error(message) => bail!("Cleanup daemon failed to handle request: {message}"),
+        response => Ok(response),
+    }
+}
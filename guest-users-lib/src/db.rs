@@ -4,6 +4,7 @@ pub mod models;
 pub mod schema;
 
 use crate::diesel::BelongingToDsl;
+use crate::diesel::BoolExpressionMethods;
 use crate::diesel::Connection;
 use crate::diesel::ExpressionMethods;
 use crate::diesel::OptionalExtension;
@@ -12,6 +13,7 @@ use crate::diesel::RunQueryDsl;
 
 use diesel_migrations::MigrationHarness;
 
+use std::convert::TryFrom;
 use std::convert::TryInto;
 use std::fs::set_permissions;
 use std::os::unix::prelude::PermissionsExt;
@@ -27,8 +29,18 @@ use nix::unistd::Group;
 use nix::unistd::Uid;
 use nix::unistd::User;
 
+use crate::naming::GuestNames;
+
 use crate::helper::Config;
 
+/// Boot ID stored for disabled users. As it never matches a real boot ID, these users cannot log in anymore.
+pub const DISABLED_BOOT_ID: &str = "disabled";
+
+/// ID type of retired and recycled user IDs
+pub const ID_TYPE_UID: &str = "uid";
+/// ID type of retired and recycled group IDs
+pub const ID_TYPE_GID: &str = "gid";
+
 pub struct DB<'a> {
     conn: diesel::SqliteConnection,
     global_settings: &'a Config,
@@ -67,7 +79,156 @@ impl<'a> DB<'a> {
         })
     }
 
-    fn find_next_unused_user_id_and_name(&mut self) -> Result<(i64, String), Error> {
+    /// Returns the retired IDs of the given type, which got prepared for recycling, oldest first
+    fn get_prepared_retired_ids(
+        &mut self,
+        retired_id_type: &str,
+    ) -> Result<Vec<models::RetiredId>, Error> {
+        use schema::retired_ids::dsl::{id_type, prepared_at, retired_at, retired_ids};
+
+        Ok(retired_ids
+            .filter(id_type.eq(retired_id_type))
+            .filter(prepared_at.is_not_null())
+            .order(retired_at.asc())
+            .load::<models::RetiredId>(&mut self.conn)?)
+    }
+
+    /// Prepares all retired IDs, which are not prepared yet, for recycling.
+    /// This scans the file system, so it is run by the cleanup daemon ahead of time instead of during login.
+    /// Failing to prepare a single ID does not affect the others, it gets retried on the next call.
+    pub fn prepare_retired_ids(&mut self) -> Result<(), Error> {
+        use schema::retired_ids::dsl::{prepared_at, retired_at, retired_ids};
+
+        let unprepared_ids = retired_ids
+            .filter(prepared_at.is_null())
+            .order(retired_at.asc())
+            .load::<models::RetiredId>(&mut self.conn)?;
+        for retired_id in unprepared_ids {
+            if let Err(e) = self.prepare_retired_id(&retired_id) {
+                log::error!(
+                    "Preparing retired {} {} for recycling failed: {e:#}",
+                    retired_id.id_type,
+                    retired_id.retired_id
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Prepares a retired ID for re-use by scanning `id_recycle_scan_paths` for files still owned by this ID.
+    /// These leftovers get handled according to `id_recycle_leftover_policy`.
+    /// If the ID can be re-used afterwards, it gets marked as prepared.
+    fn prepare_retired_id(&mut self, retired_id: &models::RetiredId) -> Result<(), Error> {
+        use schema::retired_ids::dsl::{leftover_count, leftover_policy, prepared_at};
+
+        let (uid, gid) = if retired_id.id_type == ID_TYPE_UID {
+            (Some(retired_id.retired_id.try_into()?), None)
+        } else {
+            (None, Some(retired_id.retired_id.try_into()?))
+        };
+        let policy = self.global_settings.id_recycle_leftover_policy;
+
+        log::info!(
+            "Scanning for leftovers of retired {} {}",
+            retired_id.id_type,
+            retired_id.retired_id
+        );
+        let leftover_files = crate::helper::find_files_owned_by(
+            &self.global_settings.id_recycle_scan_paths,
+            uid,
+            gid,
+        )?;
+        if !crate::helper::handle_leftover_files(policy, &leftover_files, uid, gid)? {
+            log::warn!(
+                "Retired {} {} still owns {} file(s), not recycling it",
+                retired_id.id_type,
+                retired_id.retired_id,
+                leftover_files.len()
+            );
+            return Ok(());
+        }
+
+        diesel::update(retired_id)
+            .set((
+                prepared_at.eq(chrono::Utc::now().naive_utc()),
+                leftover_count.eq(i64::try_from(leftover_files.len())?),
+                leftover_policy.eq(policy.to_string()),
+            ))
+            .execute(&mut self.conn)?;
+
+        log::info!(
+            "Prepared retired {} {} for recycling",
+            retired_id.id_type,
+            retired_id.retired_id
+        );
+        Ok(())
+    }
+
+    /// Consumes a prepared retired ID and records the recycling for auditing.
+    /// Meant to be called within the transaction creating the user, so the ID does not get lost if that fails.
+    fn recycle_retired_id(
+        conn: &mut diesel::SqliteConnection,
+        retired_id: &models::RetiredId,
+    ) -> Result<(), Error> {
+        let id_recycle = models::NewIdRecycle {
+            id_type: retired_id.id_type.clone(),
+            recycled_id: retired_id.retired_id,
+            retired_at: retired_id.retired_at,
+            recycled_at: chrono::Utc::now().naive_utc(),
+            leftover_count: retired_id.leftover_count.unwrap_or_default(),
+            leftover_policy: retired_id.leftover_policy.clone().unwrap_or_default(),
+        };
+        if diesel::delete(retired_id).execute(conn)? == 0 {
+            bail!(
+                "Retired {} {} got recycled concurrently",
+                retired_id.id_type,
+                retired_id.retired_id
+            );
+        }
+        diesel::insert_into(schema::id_recycles::dsl::id_recycles)
+            .values(&id_recycle)
+            .execute(conn)?;
+
+        log::info!("Recycled {} {}", retired_id.id_type, retired_id.retired_id);
+        Ok(())
+    }
+
+    /// Checks whether a user ID and the names rendered for it are unused.
+    /// Returns the names if all of them are available.
+    fn check_user_id_available(
+        &mut self,
+        next_user_id: i64,
+        created_at: chrono::NaiveDateTime,
+    ) -> Result<Option<GuestNames>, Error> {
+        let next_names = crate::naming::generate(self.global_settings, next_user_id, created_at)?;
+        let next_username = &next_names.user_name;
+
+        if self.find_user_by_id(next_user_id.try_into()?)?.is_some() {
+            log::debug!("User ID {next_user_id} already being used by a guest user");
+            return Ok(None);
+        }
+        if User::from_uid(Uid::from_raw(next_user_id.try_into()?))?.is_some() {
+            log::debug!("User ID {next_user_id} already being used on system");
+            return Ok(None);
+        }
+        // random names might collide with names of existing guest users
+        if self.find_user_by_name(next_username)?.is_some() {
+            log::debug!("User name {next_username} already being used by a guest user");
+            return Ok(None);
+        }
+        if User::from_name(next_username)?.is_some() {
+            log::debug!("User name {next_username} already being used on system");
+            return Ok(None);
+        }
+
+        Ok(Some(next_names))
+    }
+
+    fn find_next_unused_user_id_and_names(
+        &mut self,
+        creation_time: chrono::NaiveDateTime,
+    ) -> Result<(i64, GuestNames, Option<models::RetiredId>), Error> {
+        // `creation_time` is named differently from the `created_at` column imported here
         use schema::users::dsl::*;
 
         // find next unused ID
@@ -78,75 +239,100 @@ impl<'a> DB<'a> {
         if max_user_id < 1 {
             bail!("Negative (and 0) user ids are not supported!");
         }
-        let mut next_user_id = max_user_id;
-        let mut next_username: String;
-
-        let username_prefix = &self.global_settings.guest_username_prefix;
 
         // check whether user id or name is already being used on system
-        loop {
-            next_user_id = next_user_id.checked_add(1).unwrap();
+        for next_user_id in
+            max_user_id.checked_add(1).unwrap()..=self.global_settings.uid_maximum.into()
+        {
+            if let Some(next_names) = self.check_user_id_available(next_user_id, creation_time)? {
+                log::info!(
+                    "Next free user id is {next_user_id} with name {}",
+                    next_names.user_name
+                );
+                return Ok((next_user_id, next_names, None));
+            }
+        }
 
-            if next_user_id > self.global_settings.uid_maximum.into() {
-                bail!("No free user id found!");
+        // all user IDs got assigned, try to recycle the IDs of deleted guest users
+        for retired_id in self.get_prepared_retired_ids(ID_TYPE_UID)? {
+            if let Some(next_names) =
+                self.check_user_id_available(retired_id.retired_id, creation_time)?
+            {
+                log::info!(
+                    "Next free user id is recycled id {} with name {}",
+                    retired_id.retired_id,
+                    next_names.user_name
+                );
+                return Ok((retired_id.retired_id, next_names, Some(retired_id)));
             }
+        }
 
-            next_username = format!("{username_prefix}-{next_user_id}");
+        bail!("No free user id found!");
+    }
 
-            if User::from_uid(Uid::from_raw(next_user_id.try_into()?))?.is_some() {
-                log::debug!("User ID {next_user_id} already being used on system");
-                continue;
-            }
-            if User::from_name(&next_username)?.is_some() {
-                log::debug!("User name {next_username} already being used on system");
-                continue;
-            }
+    /// Checks whether a group ID and its corresponding group name are unused.
+    /// Returns the group name if both are available.
+    fn check_group_id_available(&mut self, next_group_id: i64) -> Result<Option<String>, Error> {
+        let group_name_prefix = &self.global_settings.guest_group_name_prefix;
+        let next_group_name = format!("{group_name_prefix}-{next_group_id}");
 
-            break;
+        if self.find_group_by_id(next_group_id.try_into()?)?.is_some() {
+            log::debug!("Group ID {next_group_id} already being used by a guest user");
+            return Ok(None);
+        }
+        if Group::from_gid(Gid::from_raw(next_group_id.try_into()?))?.is_some() {
+            log::debug!("Group ID {next_group_id} already being used on system");
+            return Ok(None);
+        }
+        if Group::from_name(&next_group_name)?.is_some() {
+            log::debug!("Group name {next_group_name} already being used on system");
+            return Ok(None);
         }
 
-        log::info!("Next free user id is {next_user_id} with name {next_username}");
-        Ok((next_user_id, next_username))
+        Ok(Some(next_group_name))
     }
 
-    fn find_next_unused_group_id_and_name(&mut self) -> Result<(i64, String), Error> {
+    fn find_next_unused_group_id_and_name(
+        &mut self,
+    ) -> Result<(i64, String, Option<models::RetiredId>), Error> {
         use schema::users::dsl::*;
 
         let mut max_group_id: i64 = self.global_settings.gid_minimum.saturating_sub(1).into();
         if let Some(cur_max_id) = users.select(diesel::dsl::max(id)).first(&mut self.conn)? {
             max_group_id = std::cmp::max(cur_max_id, max_group_id)
         }
-        let mut next_group_id = max_group_id;
-        let mut next_group_name: String;
-
-        let group_name_prefix = &self.global_settings.guest_group_name_prefix;
 
         // check whether group id or name is already being used on system
-        loop {
-            next_group_id = next_group_id.checked_add(1).unwrap();
-            next_group_name = format!("{group_name_prefix}-{next_group_id}");
-
-            if Group::from_gid(Gid::from_raw(next_group_id.try_into()?))?.is_some() {
-                log::debug!("Group ID {next_group_id} already being used on system");
-                continue;
-            }
-            if Group::from_name(&next_group_name)?.is_some() {
-                log::debug!("Group name {next_group_name} already being used on system");
-                continue;
+        for next_group_id in
+            max_group_id.checked_add(1).unwrap()..=self.global_settings.gid_maximum.into()
+        {
+            if let Some(next_group_name) = self.check_group_id_available(next_group_id)? {
+                log::info!("Next free group id is {next_group_id} with name {next_group_name}");
+                return Ok((next_group_id, next_group_name, None));
             }
-
-            break;
         }
 
-        if next_group_id > self.global_settings.gid_maximum.into() {
-            bail!("No free group id found!");
+        // all group IDs got assigned, try to recycle the IDs of deleted guest users
+        for retired_id in self.get_prepared_retired_ids(ID_TYPE_GID)? {
+            if let Some(next_group_name) = self.check_group_id_available(retired_id.retired_id)? {
+                log::info!(
+                    "Next free group id is recycled id {} with name {next_group_name}",
+                    retired_id.retired_id
+                );
+                return Ok((retired_id.retired_id, next_group_name, Some(retired_id)));
+            }
         }
-        log::info!("Next free group id is {next_group_id} with name {next_group_name}");
-        Ok((next_group_id, next_group_name))
+
+        bail!("No free group id found!");
     }
 
-    pub fn create_guest_user(&mut self) -> Result<models::User, Error> {
-        let (group_id, group_name) = self.find_next_unused_group_id_and_name()?;
+    /// Creates a new guest user on the given seat (if any), optionally protected by the hash of an unlock PIN
+    pub fn create_guest_user(
+        &mut self,
+        seat_id: Option<String>,
+        unlock_pin_hash: Option<String>,
+    ) -> Result<models::User, Error> {
+        let (group_id, group_name, retired_group_id) = self.find_next_unused_group_id_and_name()?;
 
         let target_group = models::Group {
             id: group_id,
@@ -154,16 +340,35 @@ impl<'a> DB<'a> {
         };
 
         let home_base_path = &self.global_settings.home_base_path;
-        let (user_id, username) = self.find_next_unused_user_id_and_name()?;
+        let created_at = chrono::Utc::now().naive_utc();
+        let (user_id, names, retired_user_id) =
+            self.find_next_unused_user_id_and_names(created_at)?;
         let current_boot_id = crate::helper::get_current_os_boot_id()?;
         let target_user = models::User {
             id: user_id,
             user_group_id: group_id,
-            user_name: username.clone(),
-            home_path: format!("{home_base_path}/{username}"),
+            user_name: names.user_name.clone(),
+            home_path: format!("{home_base_path}/{}", names.user_name),
             boot_id: current_boot_id,
+            created_at,
+            seat_id,
+            unlock_pin_hash,
+            human_name: Some(names.human_name),
         };
 
+        // resolve supplementary groups prior to creating anything, so we do not end up with half-created users
+        let mut supplementary_group_ids = Vec::new();
+        for supplementary_group_name in self.global_settings.guest_supplementary_groups.iter() {
+            match Group::from_name(supplementary_group_name)? {
+                Some(supplementary_group) => {
+                    supplementary_group_ids.push(i64::from(supplementary_group.gid.as_raw()))
+                }
+                None => log::warn!(
+                    "Supplementary group {supplementary_group_name} does not exist, skipping it"
+                ),
+            }
+        }
+
         crate::helper::ensure_home_base_path(self.global_settings)?;
 
         // Ensure home directory of guest user does not already exist
@@ -172,7 +377,52 @@ impl<'a> DB<'a> {
             bail!("Home path {} already exists", &target_user.home_path);
         }
 
+        // a half-created guest user would block its name, as the next attempt finds its home directory
+        if let Err(err) = self.set_up_guest_user(
+            &target_user,
+            &target_group,
+            supplementary_group_ids,
+            retired_group_id.iter().chain(retired_user_id.iter()),
+        ) {
+            log::error!(
+                "Creating user {} failed, rolling back: {err:?}",
+                target_user.user_name
+            );
+            if let Err(rollback_err) =
+                crate::helper::rollback_user_creation(self.global_settings, &target_user)
+            {
+                log::error!(
+                    "Unable to roll back creation of user {}: {rollback_err:?}",
+                    target_user.user_name
+                );
+            }
+            return Err(err);
+        }
+
+        Ok(target_user)
+    }
+
+    /// Sets up the home directory and resource limits of a new guest user and stores it in the database.
+    /// The database rows (including consumed recycled IDs) are written in a single transaction as last step,
+    /// so a failure never leaves any of them behind.
+    fn set_up_guest_user<'r>(
+        &mut self,
+        target_user: &models::User,
+        target_group: &models::Group,
+        supplementary_group_ids: Vec<i64>,
+        recycled_ids: impl Iterator<Item = &'r models::RetiredId>,
+    ) -> Result<(), Error> {
         std::fs::create_dir_all(&target_user.home_path)?;
+        match self.global_settings.home_backend {
+            crate::helper::HomeBackend::Tmpfs => {
+                crate::helper::mount_tmpfs_home(self.global_settings, target_user)?
+            }
+            crate::helper::HomeBackend::Disk => {
+                if self.global_settings.guest_home_quota_bytes > 0 {
+                    crate::helper::mount_quota_home(self.global_settings, target_user)?
+                }
+            }
+        }
         chown(
             Path::new(&target_user.home_path),
             Some(Uid::from_raw(target_user.id as u32)),
@@ -185,15 +435,42 @@ impl<'a> DB<'a> {
         )?;
 
         // copy default home directory
-        crate::helper::copy_dir_recursive_and_set_owner(
-            &self.global_settings.home_skel,
-            &target_user.home_path,
+        crate::secure_fs::copy_dir_contents(
+            Path::new(&self.global_settings.home_skel),
+            Path::new(&target_user.home_path),
             Uid::from_raw(target_user.id as u32),
             Gid::from_raw(target_user.user_group_id as u32),
-            false,
+            self.global_settings.home_skel_copy_xattrs,
         )
         .context("Unable to copy skeleton home directory to new guest users home directory!")?;
 
+        // guest specific files get layered on top of the skeleton home directory
+        for overlay in self.global_settings.home_skel_overlays.iter() {
+            if !Path::new(overlay).is_dir() {
+                log::debug!("Skeleton overlay {overlay} does not exist, skipping it");
+                continue;
+            }
+            crate::secure_fs::copy_dir_contents(
+                Path::new(overlay),
+                Path::new(&target_user.home_path),
+                Uid::from_raw(target_user.id as u32),
+                Gid::from_raw(target_user.user_group_id as u32),
+                self.global_settings.home_skel_copy_xattrs,
+            )
+            .with_context(|| {
+                format!(
+                    "Unable to copy skeleton overlay {overlay} to new guest users home directory!"
+                )
+            })?;
+        }
+        crate::helper::render_home_templates(
+            self.global_settings,
+            target_user,
+            &target_group.group_name,
+        )?;
+
+        crate::helper::install_resource_limits(self.global_settings, target_user)?;
+
         if Path::new(&target_user.home_path)
             .metadata()?
             .permissions()
@@ -206,14 +483,30 @@ impl<'a> DB<'a> {
             );
         }
 
-        diesel::insert_into(schema::groups::dsl::groups)
-            .values(&target_group)
-            .execute(&mut self.conn)?;
-        diesel::insert_into(schema::users::dsl::users)
-            .values(&target_user)
-            .execute(&mut self.conn)?;
-
-        Ok(target_user)
+        // recycled IDs only get consumed if the user got created successfully
+        self.conn.transaction::<_, Error, _>(|conn| {
+            for retired_id in recycled_ids {
+                Self::recycle_retired_id(conn, retired_id)?;
+            }
+            diesel::insert_into(schema::groups::dsl::groups)
+                .values(target_group)
+                .execute(conn)?;
+            diesel::insert_into(schema::users::dsl::users)
+                .values(target_user)
+                .execute(conn)?;
+            diesel::insert_into(schema::user_group_memberships::dsl::user_group_memberships)
+                .values(
+                    supplementary_group_ids
+                        .into_iter()
+                        .map(|supplementary_group_id| models::NewUserGroupMembership {
+                            user_id: target_user.id,
+                            group_id: supplementary_group_id,
+                        })
+                        .collect::<Vec<models::NewUserGroupMembership>>(),
+                )
+                .execute(conn)?;
+            Ok(())
+        })
     }
 
     pub fn get_users(&mut self) -> Result<Vec<models::User>, Error> {
@@ -247,6 +540,193 @@ impl<'a> DB<'a> {
         Ok(result)
     }
 
+    /// Disables a guest user, which prevents any further (re-)login of this user
+    pub fn disable_user(&mut self, user: &models::User) -> Result<(), Error> {
+        use schema::users::dsl::{boot_id, id, users};
+
+        diesel::update(users.filter(id.eq(user.id)))
+            .set(boot_id.eq(DISABLED_BOOT_ID))
+            .execute(&mut self.conn)?;
+
+        Ok(())
+    }
+
+    /// Stores the seat of a guest user, which got created without one being recorded (e.g. before seats got stored)
+    pub fn backfill_user_seat_id(
+        &mut self,
+        user: &models::User,
+        new_seat_id: &str,
+    ) -> Result<(), Error> {
+        use schema::users::dsl::{id, seat_id, users};
+
+        diesel::update(users.filter(id.eq(user.id)).filter(seat_id.is_null()))
+            .set(seat_id.eq(new_seat_id))
+            .execute(&mut self.conn)?;
+
+        Ok(())
+    }
+
+    /// Deletes a guest user together with its group and group memberships from the database.
+    /// Its IDs get retired, so they can be recycled once all IDs got assigned.
+    /// Callers have to make sure the user does not own any resources anymore.
+    pub fn delete_guest_user(&mut self, user: &models::User) -> Result<(), Error> {
+        let retired_at = chrono::Utc::now().naive_utc();
+        let retired_ids = vec![
+            models::NewRetiredId {
+                id_type: ID_TYPE_UID.to_string(),
+                retired_id: user.id,
+                retired_at,
+            },
+            models::NewRetiredId {
+                id_type: ID_TYPE_GID.to_string(),
+                retired_id: user.user_group_id,
+                retired_at,
+            },
+        ];
+
+        self.conn.transaction::<_, Error, _>(|conn| {
+            diesel::delete(models::UserGroupMembership::belonging_to(user)).execute(conn)?;
+            diesel::delete(user).execute(conn)?;
+            diesel::delete(schema::groups::dsl::groups.find(user.user_group_id)).execute(conn)?;
+            diesel::insert_into(schema::retired_ids::dsl::retired_ids)
+                .values(&retired_ids)
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+
+    /// Records the start of a session of a guest user together with its origin
+    pub fn start_session(
+        &mut self,
+        user: &models::User,
+        service: Option<String>,
+        tty: Option<String>,
+        rhost: Option<String>,
+        seat_id: Option<String>,
+    ) -> Result<models::Session, Error> {
+        use schema::sessions::dsl::{id, sessions};
+
+        let new_session = models::NewSession {
+            user_id: user.id,
+            user_name: user.user_name.clone(),
+            boot_id: crate::helper::get_current_os_boot_id()?,
+            started_at: chrono::Utc::now().naive_utc(),
+            service,
+            tty,
+            rhost,
+            seat_id,
+        };
+
+        self.conn.transaction::<_, Error, _>(|conn| {
+            diesel::insert_into(sessions)
+                .values(&new_session)
+                .execute(conn)?;
+            Ok(sessions.order(id.desc()).first::<models::Session>(conn)?)
+        })
+    }
+
+    /// Records the end of a session, returns the ended session if it was still open
+    pub fn end_session(&mut self, session_id: i64) -> Result<Option<models::Session>, Error> {
+        use schema::sessions::dsl::{ended_at, id, sessions};
+
+        self.conn.transaction::<_, Error, _>(|conn| {
+            let updated = diesel::update(
+                sessions
+                    .filter(id.eq(session_id))
+                    .filter(ended_at.is_null()),
+            )
+            .set(ended_at.eq(chrono::Utc::now().naive_utc()))
+            .execute(conn)?;
+            if updated == 0 {
+                return Ok(None);
+            }
+            Ok(Some(
+                sessions.find(session_id).first::<models::Session>(conn)?,
+            ))
+        })
+    }
+
+    pub fn find_session_by_id(
+        &mut self,
+        session_id: i64,
+    ) -> Result<Option<models::Session>, Error> {
+        use schema::sessions::dsl::sessions;
+
+        Ok(sessions
+            .find(session_id)
+            .first::<models::Session>(&mut self.conn)
+            .optional()?)
+    }
+
+    /// Returns the sessions of a guest user, which got opened during the current boot and are not ended yet
+    pub fn find_open_sessions_for_user(
+        &mut self,
+        user: &models::User,
+    ) -> Result<Vec<models::Session>, Error> {
+        use schema::sessions::dsl::{boot_id, ended_at, sessions, user_id};
+
+        Ok(sessions
+            .filter(user_id.eq(user.id))
+            .filter(boot_id.eq(crate::helper::get_current_os_boot_id()?))
+            .filter(ended_at.is_null())
+            .load::<models::Session>(&mut self.conn)?)
+    }
+
+    /// Ends all sessions of previous boots, which never got closed (e.g. due to a power loss).
+    /// As their real end is unknown, they get bounded by the given start of the current boot.
+    /// Returns the number of ended sessions.
+    pub fn end_sessions_of_previous_boots(
+        &mut self,
+        current_boot_id: &str,
+        current_boot_time: chrono::NaiveDateTime,
+    ) -> Result<usize, Error> {
+        use schema::sessions::dsl::{boot_id, end_unknown, ended_at, sessions};
+
+        Ok(diesel::update(
+            sessions
+                .filter(ended_at.is_null())
+                .filter(boot_id.ne(current_boot_id)),
+        )
+        .set((ended_at.eq(current_boot_time), end_unknown.eq(true)))
+        .execute(&mut self.conn)?)
+    }
+
+    /// Returns the recorded sessions (oldest first), optionally limited to the ones open at the given time (UTC),
+    /// a seat or a user. Sessions of a previous boot, which never got closed, are considered open until the start
+    /// of the next boot once the cleanup daemon ended them (see `end_sessions_of_previous_boots`).
+    pub fn find_sessions(
+        &mut self,
+        open_at: Option<chrono::NaiveDateTime>,
+        match_seat_id: Option<&str>,
+        match_user_name: Option<&str>,
+    ) -> Result<Vec<models::Session>, Error> {
+        use schema::sessions::dsl::{ended_at, seat_id, sessions, started_at, user_name};
+
+        let mut query = sessions.order(started_at.asc()).into_boxed();
+        if let Some(at) = open_at {
+            query = query
+                .filter(started_at.le(at))
+                .filter(ended_at.is_null().or(ended_at.ge(at)));
+        }
+        if let Some(match_seat_id) = match_seat_id {
+            query = query.filter(seat_id.eq(match_seat_id));
+        }
+        if let Some(match_user_name) = match_user_name {
+            query = query.filter(user_name.eq(match_user_name));
+        }
+
+        Ok(query.load::<models::Session>(&mut self.conn)?)
+    }
+
+    /// Returns the audit log of recycled IDs
+    pub fn get_id_recycles(&mut self) -> Result<Vec<models::IdRecycle>, Error> {
+        use schema::id_recycles::dsl::{id_recycles, recycled_at};
+
+        Ok(id_recycles
+            .order(recycled_at.asc())
+            .load::<models::IdRecycle>(&mut self.conn)?)
+    }
+
     pub fn get_groups(&mut self) -> Result<Vec<models::Group>, Error> {
         use schema::groups::dsl::groups;
 
@@ -278,6 +758,16 @@ impl<'a> DB<'a> {
         Ok(result)
     }
 
+    /// Returns all group memberships of a user.
+    /// Memberships may reference guest groups as well as system groups (supplementary groups).
+    pub fn find_groups_for_user(
+        &mut self,
+        match_user: &models::User,
+    ) -> Result<Vec<models::UserGroupMembership>, Error> {
+        Ok(models::UserGroupMembership::belonging_to(match_user)
+            .load::<models::UserGroupMembership>(&mut self.conn)?)
+    }
+
     pub fn find_users_for_group(
         &mut self,
         match_group: &models::Group,
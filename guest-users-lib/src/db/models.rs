@@ -3,6 +3,9 @@
 extern crate diesel;
 
 use crate::db::schema::groups;
+use crate::db::schema::id_recycles;
+use crate::db::schema::retired_ids;
+use crate::db::schema::sessions;
 use crate::db::schema::user_group_memberships;
 use crate::db::schema::users;
 
@@ -14,6 +17,14 @@ pub struct User {
     pub user_group_id: i64,
     pub home_path: String,
     pub boot_id: String,
+    /// Creation time (UTC)
+    pub created_at: chrono::NaiveDateTime,
+    /// logind seat the user got created on, re-login is only permitted from this seat
+    pub seat_id: Option<String>,
+    /// Argon2 hash of the PIN needed for re-login, if any
+    pub unlock_pin_hash: Option<String>,
+    /// Human readable name rendered from `guest_human_name_template`, `None` for users created before names got stored
+    pub human_name: Option<String>,
 }
 
 #[derive(Identifiable, Insertable, AsChangeset, Queryable, Debug, Clone)]
@@ -32,3 +43,91 @@ pub struct UserGroupMembership {
     pub user_id: i64,
     pub group_id: i64,
 }
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = user_group_memberships)]
+pub struct NewUserGroupMembership {
+    pub user_id: i64,
+    pub group_id: i64,
+}
+
+#[derive(Identifiable, Queryable, Debug, Clone)]
+#[diesel(table_name = retired_ids)]
+pub struct RetiredId {
+    pub id: i64,
+    /// Either `uid` or `gid`
+    pub id_type: String,
+    pub retired_id: i64,
+    pub retired_at: chrono::NaiveDateTime,
+    /// When the ID got scanned for leftovers by the cleanup daemon, `None` until it is ready to be recycled
+    pub prepared_at: Option<chrono::NaiveDateTime>,
+    /// Number of files found still being owned by the ID during preparation
+    pub leftover_count: Option<i64>,
+    pub leftover_policy: Option<String>,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = retired_ids)]
+pub struct NewRetiredId {
+    pub id_type: String,
+    pub retired_id: i64,
+    pub retired_at: chrono::NaiveDateTime,
+}
+
+#[derive(Identifiable, Queryable, Debug, Clone)]
+#[diesel(table_name = id_recycles)]
+pub struct IdRecycle {
+    pub id: i64,
+    /// Either `uid` or `gid`
+    pub id_type: String,
+    pub recycled_id: i64,
+    pub retired_at: chrono::NaiveDateTime,
+    pub recycled_at: chrono::NaiveDateTime,
+    /// Number of files found still being owned by the ID
+    pub leftover_count: i64,
+    pub leftover_policy: String,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = id_recycles)]
+pub struct NewIdRecycle {
+    pub id_type: String,
+    pub recycled_id: i64,
+    pub retired_at: chrono::NaiveDateTime,
+    pub recycled_at: chrono::NaiveDateTime,
+    pub leftover_count: i64,
+    pub leftover_policy: String,
+}
+
+#[derive(Identifiable, Queryable, Debug, Clone)]
+#[diesel(table_name = sessions)]
+pub struct Session {
+    pub id: i64,
+    pub user_id: i64,
+    pub user_name: String,
+    pub boot_id: String,
+    pub started_at: chrono::NaiveDateTime,
+    /// End time (UTC), `None` while the session is open
+    pub ended_at: Option<chrono::NaiveDateTime>,
+    /// PAM service the session got opened by (e.g. `gdm-password`)
+    pub service: Option<String>,
+    pub tty: Option<String>,
+    /// Remote host for remote sessions
+    pub rhost: Option<String>,
+    pub seat_id: Option<String>,
+    /// Whether the session never got closed during its boot, `ended_at` is the start of the next boot then
+    pub end_unknown: bool,
+}
+
+#[derive(Insertable, Debug, Clone)]
+#[diesel(table_name = sessions)]
+pub struct NewSession {
+    pub user_id: i64,
+    pub user_name: String,
+    pub boot_id: String,
+    pub started_at: chrono::NaiveDateTime,
+    pub service: Option<String>,
+    pub tty: Option<String>,
+    pub rhost: Option<String>,
+    pub seat_id: Option<String>,
+}
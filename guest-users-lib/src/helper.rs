@@ -1,49 +1,128 @@
+use std::convert::TryInto;
+
 use anyhow::Context;
 use nix::libc::{gid_t, uid_t};
 
-const CONFIG_FILE_PATH: &str = "/etc/guest-users/settings.toml";
+use crate::network_policy::NetworkPolicy;
+
+pub const CONFIG_FILE_PATH: &str = "/etc/guest-users/settings.toml";
+
+/// Directory systemd-logind stores the lingering flag of users in
+const LINGER_PATH: &str = "/var/lib/systemd/linger";
+
+/// Directory for runtime systemd unit drop-ins (cleared on reboot, just like guest sessions)
+const SYSTEMD_RUNTIME_UNIT_PATH: &str = "/run/systemd/system";
+
+/// Number of attempts to kill all processes of a user
+const KILL_USER_PROCESSES_ATTEMPTS: usize = 10;
+/// Time to wait for killed processes to exit before checking again
+const KILL_USER_PROCESSES_WAIT: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// List of strings, used for config values holding multiple entries
+pub type StringList = Vec<String>;
+
+/// Defines an enum used as config value. It gets deserialized from and displayed as the given names.
+/// Call format: config_enum!(pub enum Name { Variant1 => "name1", Variant2 => "name2", ... })
+macro_rules! config_enum {
+    ( $(#[$meta:meta])* pub enum $name:ident { $( $(#[$variant_meta:meta])* $variant:ident => $value:literal, )+ } ) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+        pub enum $name {
+            $(
+                $(#[$variant_meta])*
+                #[serde(rename = $value)]
+                $variant,
+            )+
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                match *self {
+                    $( $name::$variant => write!(f, $value), )+
+                }
+            }
+        }
+    }
+}
+pub(crate) use config_enum;
+
+config_enum!(
+    /// Storage backend for guest home directories
+    pub enum HomeBackend {
+        /// Home directories are created on the persistent disk below `home_base_path`
+        Disk => "disk",
+        /// A size-limited tmpfs gets mounted per guest home directory, so guest data never reaches the disk
+        Tmpfs => "tmpfs",
+    }
+);
+
+config_enum!(
+    /// Source used to detect whether users have active sessions
+    pub enum SessionDetection {
+        /// Parse `/var/run/utmp`
+        Utmp => "utmp",
+        /// Ask systemd-logind via D-Bus, falling back to utmp if logind is not reachable
+        Logind => "logind",
+    }
+);
+
+config_enum!(
+    /// How guest users get a PIN for unlocking their sessions
+    pub enum UnlockPinMode {
+        /// Guest users re-login without any credential
+        Off => "off",
+        /// A PIN gets generated and shown to the guest user on login
+        Generate => "generate",
+        /// The guest user chooses a PIN on login
+        Choose => "choose",
+    }
+);
+
+config_enum!(
+    /// Handling of files still owned by a retired ID, which is about to be recycled
+    pub enum LeftoverPolicy {
+        /// Do not recycle the ID
+        Refuse => "refuse",
+        /// Change the owner of the files to nobody/nogroup
+        Chown => "chown",
+        /// Delete the files
+        Delete => "delete",
+    }
+);
 
 macro_rules! config_default_item {
-    ( $z:expr, $a:ident, String ) => {
-        $z.get_string(stringify!($a))?
+    ( $c:expr, String ) => {
+        String::from($c)
     };
-    ( $z:expr, $a:ident, i64 ) => {
-        $z.get_int(stringify!($a))?
+    ( $c:expr, StringList ) => {
+        $c.into_iter().map(String::from).collect::<StringList>()
     };
-    ( $z:expr, $a:ident, uid_t ) => {
-        std::convert::TryInto::<u32>::try_into($z.get_int(stringify!($a))?)?
-    };
-    ( $z:expr, $a:ident, gid_t ) => {
-        std::convert::TryInto::<u32>::try_into($z.get_int(stringify!($a))?)?
-    };
-    ( $z:expr, $a:ident, bool ) => {
-        $z.get_bool(stringify!($a))?
+    ( $c:expr, $b:ident ) => {
+        $c
     };
 }
 
-/// Wrapper for having a config object pre-filled with default values when building via Config::default from a ConfigBuilder
+/// Wrapper for having a config object pre-filled with default values when deserializing it.
 /// When called, this macro will create a Config struct containing all config values.
-/// To fill the object, the default function can be used together with a pre-configured config::ConfigBuilder
+/// Values missing in the config file keep their defaults, unknown keys are rejected.
 /// Call format: config_default(name1, type1, default1, name2, type2, default2, ...)
 macro_rules! config_default {
     ( $( $a:ident, $b:ident, $c:expr ),+ ) => {
+        #[derive(Clone, Debug, serde::Deserialize)]
+        #[serde(default, deny_unknown_fields)]
         pub struct Config {
             $(
                 pub $a: $b,
             )+
         }
 
-        impl Config {
-            pub fn default(mut conf: config::ConfigBuilder<config::builder::DefaultState>) -> anyhow::Result<Self> {
-                $(
-                    conf = conf.set_default(stringify!($a), $c)?;
-                )+
-                let built_conf = conf.build()?;
-                Ok(Config {
+        impl Default for Config {
+            fn default() -> Self {
+                Config {
                     $(
-                        $a: config_default_item!(built_conf, $a, $b),
+                        $a: config_default_item!($c, $b),
                     )+
-                })
+                }
             }
         }
     }
@@ -55,10 +134,20 @@ config_default!(
     guest_username_prefix, String, "guest",
     guest_username_human_readable_prefix, String, "Guest",
     guest_group_name_prefix, String, "guest",
+    guest_username_template, String, "{prefix}-{uid}",
+    guest_human_name_template, String, "{prefix} ({uid})",
     home_base_path, String, "/home/guest-users",
     home_skel, String, "/etc/skel",
+    home_skel_copy_xattrs, bool, false,
+    home_skel_overlays, StringList, vec!["/etc/guest-users/skel.d"],
+    home_skel_templates, StringList, StringList::new(),
+    home_backend, HomeBackend, HomeBackend::Disk,
+    home_tmpfs_size, String, "1G",
+    guest_home_quota_bytes, i64, 0,
+    home_image_path, String, "/var/lib/guest-users/home-images",
     guest_shell, String, "/bin/bash",
     public_database_path, String, "/etc/guest-users/public.db",
+    session_detection, SessionDetection, SessionDetection::Logind,
     uid_minimum, uid_t, 31001,
     uid_maximum, uid_t, 31999,
     gid_minimum, gid_t, 31001,
@@ -66,23 +155,265 @@ config_default!(
     guest_user_warning_app_name, String, "Guest User",
     guest_user_warning_title, String, "You are using a guest account",
     guest_user_warning_body, String, "All data will be deleted on logout. Make sure to store your data on a safe location apart from this device.",
+    guest_session_max_duration, i64, 0,
+    guest_idle_timeout, i64, 0,
+    guest_unlock_pin, UnlockPinMode, UnlockPinMode::Off,
+    guest_unlock_pin_length, i64, 6,
+    guest_session_warning_title, String, "Your guest session ends soon",
+    guest_session_warning_body, String, "Your guest session will end in {minutes} minute(s). Make sure to store your data on a safe location apart from this device.",
+    guest_cpu_quota, String, "",
+    guest_memory_max, String, "",
+    guest_tasks_max, i64, 0,
+    guest_private_tmp, bool, false,
+    guest_private_tmp_paths, StringList, vec!["/tmp", "/var/tmp", "/dev/shm"],
+    guest_private_tmp_size, String, "",
+    network_policy, NetworkPolicy, NetworkPolicy::Open,
+    network_allow, StringList, StringList::new(),
+    network_deny, StringList, StringList::new(),
+    network_proxy, String, "",
+    guest_account_retention_days, i64, 0,
+    guest_account_retention_scan_paths, StringList, StringList::new(),
+    id_recycle_scan_paths, StringList, vec!["/home", "/tmp", "/var"],
+    id_recycle_leftover_policy, LeftoverPolicy, LeftoverPolicy::Chown,
     enable_ghost_user, bool, true,
     ghost_user_gecos_username, String, "Guest",
     ghost_user_uid, i64, 31000,
-    ghost_user_gid, i64, 31000
+    ghost_user_gid, i64, 31000,
+    guest_supplementary_groups, StringList, StringList::new(),
+    cleanup_reconcile_interval, i64, 15,
+    cleanup_extra_paths, StringList, vec!["/tmp", "/var/tmp", "/dev/shm", "/var/spool/cron/crontabs", "/var/spool/cups/tmp", "/run/user"]
 );
 
-pub fn get_config() -> anyhow::Result<Config> {
-    let mut builder = config::Config::builder();
+/// Maximum number of digits of unlock PINs
+const UNLOCK_PIN_MAX_LENGTH: i64 = 32;
+
+/// Returns whether a path is relative and does not leave the directory it is relative to
+fn is_home_relative_path(path: &str) -> bool {
+    let path = std::path::Path::new(path);
+    path.components().next().is_some()
+        && path
+            .components()
+            .all(|component| matches!(component, std::path::Component::Normal(_)))
+}
+
+/// Returns whether a value is a positive percentage like `200%` or `12.5%`, as accepted by systemd
+fn is_valid_percentage(value: &str) -> bool {
+    match value.strip_suffix('%') {
+        Some(number) => {
+            !number.starts_with('.')
+                && number.parse::<f64>().is_ok_and(|number| number > 0.0)
+                && number.chars().all(|c| c.is_ascii_digit() || c == '.')
+        }
+        None => false,
+    }
+}
 
+/// Returns whether a value is a memory size like `4G` (optionally with a `K`, `M`, `G`, `T`, `P` or `E` suffix),
+/// a percentage like `50%` or `infinity`, as accepted by systemd
+fn is_valid_memory_size(value: &str) -> bool {
+    let number = value
+        .strip_suffix(['K', 'M', 'G', 'T', 'P', 'E'])
+        .unwrap_or(value);
+    value == "infinity"
+        || is_valid_percentage(value)
+        || (!number.is_empty()
+            && number.chars().all(|c| c.is_ascii_digit())
+            && number.chars().any(|c| c != '0'))
+}
+
+/// Returns whether a value is a size of a tmpfs like `1G` or `10%` (see `size` option of `tmpfs(5)`).
+/// Sizes end up within the mount options, so anything else (e.g. `1G,exec`) gets rejected.
+fn is_valid_tmpfs_size(value: &str) -> bool {
+    // tmpfs neither knows infinity nor fractional percentages
+    value != "infinity" && !value.contains('.') && is_valid_memory_size(value)
+}
+
+impl Config {
+    /// Checks constraints spanning multiple values, reporting all violations at once
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut problems = Vec::new();
+
+        if self.uid_minimum == 0 {
+            problems.push("uid_minimum must not be 0 (root)".to_string());
+        }
+        if self.uid_minimum > self.uid_maximum {
+            problems.push(format!(
+                "uid_minimum ({}) must not be greater than uid_maximum ({})",
+                self.uid_minimum, self.uid_maximum
+            ));
+        }
+        if self.gid_minimum == 0 {
+            problems.push("gid_minimum must not be 0 (root)".to_string());
+        }
+        if self.gid_minimum > self.gid_maximum {
+            problems.push(format!(
+                "gid_minimum ({}) must not be greater than gid_maximum ({})",
+                self.gid_minimum, self.gid_maximum
+            ));
+        }
+        if self.enable_ghost_user {
+            if (i64::from(self.uid_minimum)..=i64::from(self.uid_maximum))
+                .contains(&self.ghost_user_uid)
+            {
+                problems.push(format!(
+                    "ghost_user_uid ({}) must not be within the guest UID range {}-{}",
+                    self.ghost_user_uid, self.uid_minimum, self.uid_maximum
+                ));
+            }
+            if (i64::from(self.gid_minimum)..=i64::from(self.gid_maximum))
+                .contains(&self.ghost_user_gid)
+            {
+                problems.push(format!(
+                    "ghost_user_gid ({}) must not be within the guest GID range {}-{}",
+                    self.ghost_user_gid, self.gid_minimum, self.gid_maximum
+                ));
+            }
+        }
+
+        let home_base_path = std::path::Path::new(&self.home_base_path);
+        if home_base_path.is_absolute() && home_base_path.parent().is_none() {
+            problems.push("home_base_path must not be /".to_string());
+        }
+        let mut paths = vec![
+            ("home_base_path", &self.home_base_path),
+            ("home_skel", &self.home_skel),
+            ("home_image_path", &self.home_image_path),
+        ];
+        for (name, path_list) in [
+            ("home_skel_overlays", &self.home_skel_overlays),
+            ("guest_private_tmp_paths", &self.guest_private_tmp_paths),
+            ("cleanup_extra_paths", &self.cleanup_extra_paths),
+        ] {
+            paths.extend(path_list.iter().map(|path| (name, path)));
+        }
+        for (name, path) in paths {
+            if !std::path::Path::new(path).is_absolute() {
+                problems.push(format!("{name} '{path}' has to be an absolute path"));
+            }
+        }
+        // scanning the whole file system would take ages and cross into every other user's files
+        for (name, path_list) in [
+            ("id_recycle_scan_paths", &self.id_recycle_scan_paths),
+            (
+                "guest_account_retention_scan_paths",
+                &self.guest_account_retention_scan_paths,
+            ),
+        ] {
+            for path in path_list.iter() {
+                let scan_path = std::path::Path::new(path);
+                if !scan_path.is_absolute() {
+                    problems.push(format!("{name} '{path}' has to be an absolute path"));
+                } else if scan_path.parent().is_none() {
+                    problems.push(format!("{name} must not contain /"));
+                }
+            }
+        }
+        for template in self.home_skel_templates.iter() {
+            if !is_home_relative_path(template) {
+                problems.push(format!(
+                    "home_skel_templates '{template}' has to be relative to the home directory without any '..'"
+                ));
+            }
+        }
+
+        if self.guest_unlock_pin != UnlockPinMode::Off
+            && !(1..=UNLOCK_PIN_MAX_LENGTH).contains(&self.guest_unlock_pin_length)
+        {
+            problems.push(format!(
+                "guest_unlock_pin_length ({}) has to be between 1 and {UNLOCK_PIN_MAX_LENGTH}",
+                self.guest_unlock_pin_length
+            ));
+        }
+        if !self.guest_cpu_quota.is_empty() && !is_valid_percentage(&self.guest_cpu_quota) {
+            problems.push(format!(
+                "guest_cpu_quota '{}' has to be a percentage like 200%",
+                self.guest_cpu_quota
+            ));
+        }
+        if !self.guest_memory_max.is_empty() && !is_valid_memory_size(&self.guest_memory_max) {
+            problems.push(format!(
+                "guest_memory_max '{}' has to be a size like 4G, a percentage like 50% or infinity",
+                self.guest_memory_max
+            ));
+        }
+        if !is_valid_tmpfs_size(&self.home_tmpfs_size) {
+            problems.push(format!(
+                "home_tmpfs_size '{}' has to be a size like 1G or a percentage like 10%",
+                self.home_tmpfs_size
+            ));
+        }
+        if !self.guest_private_tmp_size.is_empty()
+            && !is_valid_tmpfs_size(&self.guest_private_tmp_size)
+        {
+            problems.push(format!(
+                "guest_private_tmp_size '{}' has to be a size like 1G or a percentage like 10%",
+                self.guest_private_tmp_size
+            ));
+        }
+        for (name, value) in [
+            ("guest_home_quota_bytes", self.guest_home_quota_bytes),
+            (
+                "guest_session_max_duration",
+                self.guest_session_max_duration,
+            ),
+            ("guest_idle_timeout", self.guest_idle_timeout),
+            ("guest_tasks_max", self.guest_tasks_max),
+            (
+                "guest_account_retention_days",
+                self.guest_account_retention_days,
+            ),
+            (
+                "cleanup_reconcile_interval",
+                self.cleanup_reconcile_interval,
+            ),
+        ] {
+            if value < 0 {
+                problems.push(format!("{name} ({value}) must not be negative"));
+            }
+        }
+        if let Err(err) = crate::naming::validate_templates(self) {
+            problems.push(format!("{err:#}"));
+        }
+        if let Err(err) = crate::network_policy::generate_ruleset(self) {
+            problems.push(format!("{err:#}"));
+        }
+
+        if !problems.is_empty() {
+            bail!(
+                "Invalid configuration:\n{}",
+                problems
+                    .iter()
+                    .map(|problem| format!("  - {problem}"))
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Loads and validates the configuration from the given TOML file
+pub fn load_config(path: &std::path::Path) -> anyhow::Result<Config> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read config file {}", path.display()))?;
+    let config: Config = toml::from_str(&content)
+        .with_context(|| format!("Unable to parse config file {}", path.display()))?;
+    config
+        .validate()
+        .with_context(|| format!("Config file {} is invalid", path.display()))?;
+    Ok(config)
+}
+
+pub fn get_config() -> anyhow::Result<Config> {
     // Only load config if config file really exists
-    if std::path::Path::new(&CONFIG_FILE_PATH).exists() {
-        builder = builder.add_source(config::File::with_name(CONFIG_FILE_PATH));
-    } else {
-        log::debug!("Config file {CONFIG_FILE_PATH} does not exist")
+    if std::path::Path::new(CONFIG_FILE_PATH).exists() {
+        return load_config(std::path::Path::new(CONFIG_FILE_PATH));
     }
+    log::debug!("Config file {CONFIG_FILE_PATH} does not exist");
 
-    Config::default(builder)
+    let config = Config::default();
+    config.validate()?;
+    Ok(config)
 }
 
 pub fn init_logger() {
@@ -101,6 +432,331 @@ pub fn get_current_os_boot_id() -> anyhow::Result<String> {
     Ok(random_boot_id.trim_end_matches(['\n']).to_string())
 }
 
+/// Returns the point in time (UTC) the system got booted at
+pub fn get_current_os_boot_time() -> anyhow::Result<chrono::NaiveDateTime> {
+    let stat = std::fs::read_to_string("/proc/stat").context("Unable to read /proc/stat")?;
+    let boot_time = stat
+        .lines()
+        .find_map(|line| line.strip_prefix("btime "))
+        .context("No boot time found in /proc/stat")?
+        .trim()
+        .parse::<i64>()
+        .context("Invalid boot time in /proc/stat")?;
+    Ok(chrono::DateTime::from_timestamp(boot_time, 0)
+        .context("Boot time out of range")?
+        .naive_utc())
+}
+
+/// Returns the point in time (UTC) at which the session of a guest user has to end due to `guest_session_max_duration`.
+/// Returns `None` if session durations are not limited.
+pub fn session_deadline(
+    settings: &Config,
+    user: &crate::db::models::User,
+) -> Option<chrono::NaiveDateTime> {
+    if settings.guest_session_max_duration <= 0 {
+        return None;
+    }
+    Some(user.created_at + chrono::Duration::minutes(settings.guest_session_max_duration))
+}
+
+/// Returns whether a guest user exceeded its maximum session duration
+pub fn is_session_deadline_exceeded(settings: &Config, user: &crate::db::models::User) -> bool {
+    session_deadline(settings, user)
+        .map(|deadline| deadline <= chrono::Utc::now().naive_utc())
+        .unwrap_or(false)
+}
+
+/// Returns whether a guest user exceeded the retention period and might get deleted (if it does not own resources anymore)
+pub fn is_retention_period_exceeded(settings: &Config, user: &crate::db::models::User) -> bool {
+    if settings.guest_account_retention_days <= 0 {
+        return false;
+    }
+    user.created_at + chrono::Duration::days(settings.guest_account_retention_days)
+        <= chrono::Utc::now().naive_utc()
+}
+
+/// Returns the real, effective, saved and file system UID of a process as listed in `/proc/<pid>/status`.
+/// The owner of `/proc/<pid>` cannot be used, as it is root for non-dumpable processes.
+fn process_uids(pid: i32) -> Option<Vec<uid_t>> {
+    // processes might have exited in the meantime
+    let status = std::fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    let uids = status.lines().find_map(|line| line.strip_prefix("Uid:"))?;
+    uids.split_whitespace()
+        .map(|uid| uid.parse::<uid_t>().ok())
+        .collect()
+}
+
+/// Returns the IDs of all processes running with the given UID (as real, effective, saved or file system UID)
+fn uid_process_ids(uid: uid_t) -> anyhow::Result<Vec<nix::unistd::Pid>> {
+    let mut pids = Vec::new();
+    for entry_res in std::fs::read_dir("/proc").context("Unable to read_dir /proc")? {
+        let entry = entry_res?;
+        let pid = match entry.file_name().to_string_lossy().parse::<i32>() {
+            Ok(pid) => pid,
+            Err(_) => continue,
+        };
+        if process_uids(pid).is_some_and(|uids| uids.contains(&uid)) {
+            pids.push(nix::unistd::Pid::from_raw(pid));
+        }
+    }
+    Ok(pids)
+}
+
+/// Returns whether any process is running with the given UID
+pub fn uid_has_processes(uid: uid_t) -> anyhow::Result<bool> {
+    Ok(!uid_process_ids(uid)?.is_empty())
+}
+
+/// Kills all processes of the given UID, so they do not keep running (and writing files) after the session ended.
+/// Prefers killing the whole logind user slice via `cgroup.kill` and falls back to killing every single process.
+pub fn kill_user_processes(uid: uid_t) -> anyhow::Result<()> {
+    let cgroup_kill_path = format!("/sys/fs/cgroup/user.slice/user-{uid}.slice/cgroup.kill");
+    if std::path::Path::new(&cgroup_kill_path).exists() {
+        log::info!("Killing user slice of UID {uid}");
+        if let Err(err) = std::fs::write(&cgroup_kill_path, "1") {
+            log::warn!("Unable to kill user slice of UID {uid}: {err:?}");
+        }
+    }
+
+    // processes might be outside of the user slice (e.g. started via cron) or fork while being killed
+    for _ in 0..KILL_USER_PROCESSES_ATTEMPTS {
+        let pids = uid_process_ids(uid)?;
+        if pids.is_empty() {
+            return Ok(());
+        }
+        for pid in pids {
+            log::debug!("Killing process {pid} of UID {uid}");
+            match nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGKILL) {
+                Ok(()) | Err(nix::errno::Errno::ESRCH) => {}
+                Err(err) => log::warn!("Unable to kill process {pid} of UID {uid}: {err:?}"),
+            }
+        }
+        std::thread::sleep(KILL_USER_PROCESSES_WAIT);
+    }
+
+    if uid_has_processes(uid)? {
+        bail!("UID {uid} still has running processes after killing them");
+    }
+    Ok(())
+}
+
+/// Searches the given paths recursively for files owned by the given UID or GID.
+/// Symlinks are not followed and the search does not cross file system boundaries.
+pub fn find_files_owned_by(
+    paths: &[String],
+    uid: Option<uid_t>,
+    gid: Option<gid_t>,
+) -> anyhow::Result<Vec<std::path::PathBuf>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let mut owned_files = Vec::new();
+    for path in paths {
+        let root_metadata = match std::fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).with_context(|| format!("Unable to scan {path}")),
+        };
+
+        let mut dir_queue = std::collections::LinkedList::new();
+        dir_queue.push_back(std::path::PathBuf::from(path));
+        while let Some(dir) = dir_queue.pop_front() {
+            // files might vanish while scanning, e.g. in /tmp
+            let metadata = match std::fs::symlink_metadata(&dir) {
+                Ok(metadata) => metadata,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err).with_context(|| format!("Unable to scan {dir:?}")),
+            };
+            if Some(metadata.uid()) == uid || Some(metadata.gid()) == gid {
+                owned_files.push(dir.clone());
+            }
+            if !metadata.is_dir() || metadata.dev() != root_metadata.dev() {
+                continue;
+            }
+
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err).with_context(|| format!("Unable to read_dir {dir:?}")),
+            };
+            for entry_res in entries {
+                dir_queue.push_back(entry_res?.path());
+            }
+        }
+    }
+    Ok(owned_files)
+}
+
+/// UID/GID of nobody/nogroup, which get leftover files assigned to
+const NOBODY_ID: u32 = 65534;
+
+/// Handles files still owned by a retired UID or GID according to the leftover policy.
+/// Returns whether the ID is safe to be recycled afterwards.
+pub fn handle_leftover_files(
+    policy: LeftoverPolicy,
+    leftover_files: &[std::path::PathBuf],
+    uid: Option<uid_t>,
+    gid: Option<gid_t>,
+) -> anyhow::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    if leftover_files.is_empty() {
+        return Ok(true);
+    }
+
+    match policy {
+        LeftoverPolicy::Refuse => return Ok(false),
+        LeftoverPolicy::Chown => {
+            for path in leftover_files {
+                let metadata = std::fs::symlink_metadata(path)?;
+                let new_uid = if Some(metadata.uid()) == uid {
+                    Some(NOBODY_ID)
+                } else {
+                    None
+                };
+                let new_gid = if Some(metadata.gid()) == gid {
+                    Some(NOBODY_ID)
+                } else {
+                    None
+                };
+                log::info!("Changing owner of leftover file {path:?} to nobody");
+                std::os::unix::fs::lchown(path, new_uid, new_gid)
+                    .with_context(|| format!("Unable to chown leftover file {path:?}"))?;
+            }
+        }
+        LeftoverPolicy::Delete => {
+            for path in leftover_files {
+                // files might be gone already as their parent directory got deleted before
+                let metadata = match std::fs::symlink_metadata(path) {
+                    Ok(metadata) => metadata,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                    Err(err) => return Err(err.into()),
+                };
+                log::info!("Removing leftover file {path:?}");
+                if metadata.is_dir() {
+                    let skipped = crate::secure_fs::remove_dir_all(path)
+                        .with_context(|| format!("Unable to remove leftover file {path:?}"))?;
+                    // files below the skipped mount points might still be owned by the ID
+                    if !skipped.is_empty() {
+                        log::warn!("Unable to remove leftover directory {path:?} completely, mount points {skipped:?} are left");
+                        return Ok(false);
+                    }
+                } else {
+                    std::fs::remove_file(path)
+                        .with_context(|| format!("Unable to remove leftover file {path:?}"))?;
+                }
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Generates a random numeric PIN with the given number of digits
+pub fn generate_unlock_pin(length: i64) -> String {
+    use rand::Rng;
+
+    let mut rng = rand::rngs::OsRng;
+    (0..length)
+        .map(|_| char::from(b'0' + rng.gen_range(0..10)))
+        .collect()
+}
+
+/// Hashes an unlock PIN with a random salt
+pub fn hash_unlock_pin(pin: &str) -> anyhow::Result<String> {
+    use argon2::PasswordHasher;
+
+    let salt = argon2::password_hash::SaltString::generate(&mut rand::rngs::OsRng);
+    Ok(argon2::Argon2::default()
+        .hash_password(pin.as_bytes(), &salt)
+        .map_err(|err| anyhow!("Unable to hash unlock PIN: {err}"))?
+        .to_string())
+}
+
+/// Verifies an unlock PIN against its hash
+pub fn verify_unlock_pin(pin: &str, pin_hash: &str) -> anyhow::Result<bool> {
+    use argon2::PasswordVerifier;
+
+    let parsed_hash = argon2::PasswordHash::new(pin_hash)
+        .map_err(|err| anyhow!("Unable to parse unlock PIN hash: {err}"))?;
+    Ok(argon2::Argon2::default()
+        .verify_password(pin.as_bytes(), &parsed_hash)
+        .is_ok())
+}
+
+/// Returns the human readable name of a guest user (e.g. shown on login screens)
+pub fn human_readable_user_name(settings: &Config, user: &crate::db::models::User) -> String {
+    // users created before human readable names got stored keep their previous name
+    user.human_name.clone().unwrap_or_else(|| {
+        format!(
+            "{} ({})",
+            settings.guest_username_human_readable_prefix, user.id
+        )
+    })
+}
+
+/// Replaces all `{{name}}` placeholders of the given values within a template, unknown placeholders are kept
+fn fill_template(template: &str, values: &[(&str, String)]) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |content, (name, value)| {
+            content.replace(&format!("{{{{{name}}}}}"), value)
+        })
+}
+
+/// Substitutes placeholders like `{{user_name}}` within the `home_skel_templates` of a new home directory.
+/// Template paths are relative to the home directory and must not contain symlinks, so no file outside of it gets touched.
+pub fn render_home_templates(
+    settings: &Config,
+    user: &crate::db::models::User,
+    group_name: &str,
+) -> anyhow::Result<()> {
+    let home_path = std::path::Path::new(&user.home_path);
+    let values = [
+        ("user_name", user.user_name.clone()),
+        ("uid", user.id.to_string()),
+        ("gid", user.user_group_id.to_string()),
+        ("group_name", group_name.to_string()),
+        ("human_name", human_readable_user_name(settings, user)),
+        ("home", user.home_path.clone()),
+    ];
+
+    for template in settings.home_skel_templates.iter() {
+        if !is_home_relative_path(template) {
+            bail!("Template path {template} has to be relative to the home directory without any '..'");
+        }
+        let relative_path = std::path::Path::new(template);
+
+        // check every component, as copied symlinks might point anywhere
+        let mut path = home_path.to_path_buf();
+        let mut is_file = true;
+        for component in relative_path.components() {
+            path.push(component);
+            match std::fs::symlink_metadata(&path) {
+                Ok(metadata) if metadata.file_type().is_symlink() => {
+                    bail!("Template path {path:?} contains a symlink, refusing to render it!")
+                }
+                Ok(metadata) => is_file = metadata.is_file(),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    is_file = false;
+                    break;
+                }
+                Err(err) => return Err(err).with_context(|| format!("Unable to stat {path:?}")),
+            }
+        }
+        if !is_file {
+            log::warn!("Template {path:?} is not a file, skipping it");
+            continue;
+        }
+
+        log::debug!("Rendering template {path:?}");
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Unable to read template {path:?}"))?;
+        // writing to the existing file keeps its owner and mode
+        std::fs::write(&path, fill_template(&content, &values))
+            .with_context(|| format!("Unable to write template {path:?}"))?;
+    }
+    Ok(())
+}
+
 /// Creates home base path if it does not exist yet and ensures correct permissions on it.
 pub fn ensure_home_base_path(settings: &Config) -> anyhow::Result<()> {
     std::fs::create_dir_all(&settings.home_base_path)
@@ -119,62 +775,105 @@ pub fn ensure_home_base_path(settings: &Config) -> anyhow::Result<()> {
     Ok(())
 }
 
-/// Copies a directory and all of its contents and sets to all files a new owner but preserves the access rights.
-/// Whether it preserves the access rights, sets the owner and creates the topmost directory if it does not exists
-/// can be configured via `touch_topmost_directory`
-pub fn copy_dir_recursive_and_set_owner(
-    gsrc: impl Into<std::path::PathBuf>,
-    gdst: impl Into<std::path::PathBuf>,
-    uid: nix::unistd::Uid,
-    gid: nix::unistd::Gid,
-    touch_topmost_directory: bool,
-) -> anyhow::Result<()> {
-    // list of tuple (source, destination, touch_directory)
-    let mut dir_queue: std::collections::LinkedList<(
-        std::path::PathBuf,
-        std::path::PathBuf,
-        bool,
-    )> = std::collections::LinkedList::new();
-    dir_queue.push_back((gsrc.into(), gdst.into(), touch_topmost_directory));
-
-    while let Some((src, dst, touch_directory)) = dir_queue.pop_front() {
-        if !dst.is_dir() {
-            if touch_directory {
-                std::fs::create_dir(&dst)
-                    .with_context(|| format!("Unable to create directory {dst:?}"))?;
-            } else {
-                bail!(
-                    "Directory {dst:?} does not exist, but we are not allowed to create it either."
-                );
-            }
-        }
-        if touch_directory {
-            nix::unistd::chown(&dst, Some(uid), Some(gid))
-                .with_context(|| format!("Unable to chown {dst:?}"))?;
-            std::fs::set_permissions(&dst, std::fs::metadata(&src)?.permissions())
-                .with_context(|| format!("Unable to set permissions for path {dst:?}!"))?;
-        }
+/// Mounts a size-limited tmpfs onto the (already existing) home directory of a guest user.
+pub fn mount_tmpfs_home(settings: &Config, user: &crate::db::models::User) -> anyhow::Result<()> {
+    let mount_options = format!(
+        "size={},mode=0700,uid={},gid={}",
+        settings.home_tmpfs_size, user.id, user.user_group_id
+    );
+    log::debug!(
+        "Mounting tmpfs on {} with options {mount_options}",
+        user.home_path
+    );
+    nix::mount::mount(
+        Some("tmpfs"),
+        std::path::Path::new(&user.home_path),
+        Some("tmpfs"),
+        nix::mount::MsFlags::MS_NOSUID | nix::mount::MsFlags::MS_NODEV,
+        Some(mount_options.as_str()),
+    )
+    .with_context(|| format!("Unable to mount tmpfs on {}", user.home_path))?;
+    Ok(())
+}
 
-        for entry_res in
-            std::fs::read_dir(&src).with_context(|| format!("Unable to read_dir {src:?}"))?
-        {
-            let entry = entry_res?;
-            if entry.file_type()?.is_dir() {
-                dir_queue.push_back((entry.path(), dst.join(entry.file_name()), true));
-            } else {
-                let target = dst.join(entry.file_name());
-                std::fs::copy(entry.path(), &target)?;
-                nix::unistd::chown(&target, Some(uid), Some(gid))
-                    .with_context(|| format!("Unable to chown {dst:?}"))?;
-            }
-        }
+/// Runs an external command and fails if it does not exit successfully.
+fn run_command(command: &mut std::process::Command) -> anyhow::Result<()> {
+    log::debug!("Running {command:?}");
+    let status = command
+        .status()
+        .with_context(|| format!("Unable to run {command:?}"))?;
+    if !status.success() {
+        bail!("Command {command:?} failed with {status}");
     }
+    Ok(())
+}
 
+/// Path of the image file backing the home directory of a guest user if home quotas are enabled
+pub fn home_image_path(settings: &Config, user: &crate::db::models::User) -> std::path::PathBuf {
+    std::path::Path::new(&settings.home_image_path).join(format!("{}.img", user.user_name))
+}
+
+/// Enforces the home quota of a guest user by loop-mounting a fixed-size ext4 image onto its (already existing) home directory.
+pub fn mount_quota_home(settings: &Config, user: &crate::db::models::User) -> anyhow::Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::create_dir_all(&settings.home_image_path)
+        .context("Unable to create home image directory!")?;
+    std::fs::set_permissions(
+        std::path::Path::new(&settings.home_image_path),
+        std::os::unix::fs::PermissionsExt::from_mode(0o700),
+    )
+    .context("Unable to set permissions for home image directory!")?;
+
+    // create_new ensures we never re-use an image somebody else placed there
+    let image_path = home_image_path(settings, user);
+    let image = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&image_path)
+        .with_context(|| format!("Unable to create home image {image_path:?}"))?;
+    image
+        .set_len(settings.guest_home_quota_bytes.try_into()?)
+        .with_context(|| format!("Unable to allocate home image {image_path:?}"))?;
+    drop(image);
+
+    run_command(
+        std::process::Command::new("/sbin/mkfs.ext4")
+            .args(["-q", "-F", "-m", "0", "-E"])
+            .arg(format!("root_owner={}:{}", user.id, user.user_group_id))
+            .arg(&image_path),
+    )?;
+    run_command(
+        std::process::Command::new("/bin/mount")
+            .args(["-o", "loop,nosuid,nodev"])
+            .arg(&image_path)
+            .arg(&user.home_path),
+    )?;
+
+    // mkfs creates a lost+found directory, which is of no use for guest users
+    std::fs::remove_dir(std::path::Path::new(&user.home_path).join("lost+found"))
+        .context("Unable to remove lost+found directory from home image")?;
     Ok(())
 }
 
-/// Returns whether a user has running/active sessions
-pub fn has_active_user_sessions(user_name: &str) -> anyhow::Result<bool> {
+/// Returns whether something is mounted on the given path by comparing its device to the one of its parent directory.
+pub fn is_mount_point(path: &std::path::Path) -> anyhow::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let parent = match path.parent() {
+        Some(parent) => parent,
+        None => return Ok(true), // the root directory is always a mount point
+    };
+    let path_metadata = std::fs::symlink_metadata(path)
+        .with_context(|| format!("Unable to get metadata of {path:?}"))?;
+    let parent_metadata = std::fs::symlink_metadata(parent)
+        .with_context(|| format!("Unable to get metadata of {parent:?}"))?;
+    Ok(path_metadata.dev() != parent_metadata.dev())
+}
+
+/// Returns whether a user has running/active sessions according to utmp
+fn has_active_utmp_sessions(user_name: &str) -> anyhow::Result<bool> {
     let utmp_entries =
         utmp_rs::parse_from_path("/var/run/utmp").context("Parsing /var/run/utmp failed!")?;
     let has_session = utmp_entries
@@ -190,3 +889,491 @@ pub fn has_active_user_sessions(user_name: &str) -> anyhow::Result<bool> {
         .any(|user_name_proc| user_name_proc == user_name);
     Ok(has_session)
 }
+
+/// Returns whether a user has running/active sessions, using the configured `session_detection`.
+/// Must not be called from within an async runtime, use `has_active_user_sessions_async` there.
+pub fn has_active_user_sessions(settings: &Config, user_name: &str) -> anyhow::Result<bool> {
+    if settings.session_detection == SessionDetection::Logind {
+        match crate::logind::has_active_user_sessions_blocking(user_name) {
+            Ok(has_session) => return Ok(has_session),
+            Err(err) => log::warn!("Unable to query logind, falling back to utmp: {err:?}"),
+        }
+    }
+    has_active_utmp_sessions(user_name)
+}
+
+/// Returns whether a user has running/active sessions, using the configured `session_detection`
+pub async fn has_active_user_sessions_async(
+    settings: &Config,
+    system_connection: &zbus::Connection,
+    user_name: &str,
+) -> anyhow::Result<bool> {
+    if settings.session_detection == SessionDetection::Logind {
+        match crate::logind::has_active_user_sessions(system_connection, user_name).await {
+            Ok(has_session) => return Ok(has_session),
+            Err(err) => log::warn!("Unable to query logind, falling back to utmp: {err:?}"),
+        }
+    }
+    has_active_utmp_sessions(user_name)
+}
+
+/// Path of the systemd drop-in setting the resource limits of the user slice of a guest user
+fn resource_limits_drop_in_path(user: &crate::db::models::User) -> std::path::PathBuf {
+    std::path::Path::new(SYSTEMD_RUNTIME_UNIT_PATH)
+        .join(format!("user-{}.slice.d", user.id))
+        .join("50-guest-users.conf")
+}
+
+/// Limits the resources of a guest user by installing a drop-in for its systemd user slice (`user-<uid>.slice`).
+/// Does nothing if neither `guest_cpu_quota`, `guest_memory_max` nor `guest_tasks_max` is set.
+pub fn install_resource_limits(
+    settings: &Config,
+    user: &crate::db::models::User,
+) -> anyhow::Result<()> {
+    let mut properties = Vec::new();
+    if !settings.guest_cpu_quota.is_empty() {
+        properties.push(format!("CPUQuota={}", settings.guest_cpu_quota));
+    }
+    if !settings.guest_memory_max.is_empty() {
+        properties.push(format!("MemoryMax={}", settings.guest_memory_max));
+    }
+    if settings.guest_tasks_max > 0 {
+        properties.push(format!("TasksMax={}", settings.guest_tasks_max));
+    }
+    if properties.is_empty() {
+        return Ok(());
+    }
+    // values end up in a unit file, so they must not be able to add further settings
+    if properties.iter().any(|property| property.contains('\n')) {
+        bail!("Resource limits must not contain line breaks!");
+    }
+
+    let drop_in_path = resource_limits_drop_in_path(user);
+    log::info!(
+        "Installing resource limits {drop_in_path:?} for user {}",
+        user.user_name
+    );
+    if let Some(drop_in_dir) = drop_in_path.parent() {
+        std::fs::create_dir_all(drop_in_dir)
+            .with_context(|| format!("Unable to create {drop_in_dir:?}"))?;
+    }
+    std::fs::write(
+        &drop_in_path,
+        format!(
+            "# Installed by guest-users, removed once the guest session ended\n[Slice]\n{}\n",
+            properties.join("\n")
+        ),
+    )
+    .with_context(|| format!("Unable to write {drop_in_path:?}"))?;
+    run_command(std::process::Command::new("/bin/systemctl").arg("daemon-reload"))
+}
+
+/// Removes the resource limits of a guest user installed by `install_resource_limits`
+pub fn remove_resource_limits(user: &crate::db::models::User) -> anyhow::Result<()> {
+    let drop_in_path = resource_limits_drop_in_path(user);
+    if !drop_in_path.exists() {
+        return Ok(());
+    }
+
+    log::info!(
+        "Removing resource limits {drop_in_path:?} of user {}",
+        user.user_name
+    );
+    std::fs::remove_file(&drop_in_path)
+        .with_context(|| format!("Unable to remove {drop_in_path:?}"))?;
+    if let Some(drop_in_dir) = drop_in_path.parent() {
+        // other drop-ins might exist
+        if let Err(err) = std::fs::remove_dir(drop_in_dir) {
+            log::debug!("Not removing {drop_in_dir:?}: {err:?}");
+        }
+    }
+    run_command(std::process::Command::new("/bin/systemctl").arg("daemon-reload"))
+}
+
+/// Removes the home directory of a guest user if it still exists.
+/// Beforehand, all leftover processes of the user get killed and lingering gets disabled.
+/// As a failsafe, the home directory is only removed if it is located within `home_base_path`.
+/// Mount points within the home directory are left in place (see `secure_fs::remove_dir_all`).
+/// Returns whether the home directory got removed completely.
+pub fn cleanup_user_home(
+    settings: &Config,
+    user: &crate::db::models::User,
+) -> anyhow::Result<bool> {
+    let home_path = std::path::Path::new(&user.home_path);
+    if !home_path.exists() {
+        return Ok(false);
+    }
+
+    let home_base_path = std::path::Path::new(&settings.home_base_path);
+    if !home_path.starts_with(home_base_path) {
+        log::warn!("{home_path:?} not in home_base_path={home_base_path:?}, skipping deletion!");
+        return Ok(false);
+    }
+
+    // home directories backed by tmpfs (or anything else mounted on them) need to be unmounted first
+    if is_mount_point(home_path)? {
+        log::info!(
+            "Unmounting home directory {home_path:?} of user {}",
+            user.user_name
+        );
+        nix::mount::umount2(home_path, nix::mount::MntFlags::MNT_DETACH)
+            .with_context(|| format!("Unmounting home directory of {} failed!", user.user_name))?;
+    }
+
+    // leftover processes (user services, nohup jobs, ...) might keep writing into the home directory
+    kill_user_processes(user.id.try_into()?)?;
+
+    // guest users must not keep processes running beyond their sessions
+    let linger_path = std::path::Path::new(LINGER_PATH).join(&user.user_name);
+    if linger_path.exists() {
+        log::info!("Disabling lingering of user {}", user.user_name);
+        std::fs::remove_file(&linger_path)
+            .with_context(|| format!("Disabling lingering of {} failed!", user.user_name))?;
+    }
+
+    log::info!(
+        "Removing home directory {home_path:?} of user {}",
+        user.user_name
+    );
+    // guest users control the contents of their home directory, so do not follow any symlinks or mounts within it
+    let skipped = crate::secure_fs::remove_dir_all(home_path)
+        .with_context(|| format!("Removing home directory of {} failed!", user.user_name))?;
+    for mount_point in skipped.iter() {
+        log::warn!(
+            "Left mount point {mount_point:?} within home directory of user {} in place",
+            user.user_name
+        );
+    }
+
+    // release the quota allocation of the home directory
+    let image_path = home_image_path(settings, user);
+    if image_path.exists() {
+        log::info!(
+            "Removing home image {image_path:?} of user {}",
+            user.user_name
+        );
+        std::fs::remove_file(&image_path)
+            .with_context(|| format!("Removing home image of {} failed!", user.user_name))?;
+    }
+    Ok(skipped.is_empty())
+}
+
+/// Removes everything `DB::create_guest_user` set up for a guest user, which could not be created completely:
+/// its home directory (unmounting it first), its home image and its resource limits.
+pub fn rollback_user_creation(
+    settings: &Config,
+    user: &crate::db::models::User,
+) -> anyhow::Result<()> {
+    // creating the user might have failed before its home directory got created, which leaves nothing to remove
+    let home_result = if std::path::Path::new(&user.home_path).exists() {
+        cleanup_user_home(settings, user)
+    } else {
+        Ok(true)
+    };
+
+    // the image might have been created without the home directory being mounted
+    let image_path = home_image_path(settings, user);
+    if image_path.exists() {
+        std::fs::remove_file(&image_path)
+            .with_context(|| format!("Removing home image {image_path:?} failed!"))?;
+    }
+    remove_resource_limits(user)?;
+
+    if !home_result? {
+        bail!(
+            "Home directory {} got not removed completely",
+            user.home_path
+        );
+    }
+    Ok(())
+}
+
+/// Removes all files owned by a guest user within `cleanup_extra_paths`.
+/// As a failsafe, only files located within these paths and on the same file system get removed.
+pub fn cleanup_user_files(settings: &Config, user: &crate::db::models::User) -> anyhow::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let uid = user.id.try_into()?;
+    let gid = user.user_group_id.try_into()?;
+    for extra_path in settings.cleanup_extra_paths.iter() {
+        let extra_path_buf = std::path::Path::new(extra_path);
+        let root_dev = match std::fs::symlink_metadata(extra_path_buf) {
+            Ok(metadata) => metadata.dev(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).with_context(|| format!("Unable to scan {extra_path}")),
+        };
+
+        for path in find_files_owned_by(std::slice::from_ref(extra_path), Some(uid), Some(gid))? {
+            if path == extra_path_buf || !path.starts_with(extra_path_buf) {
+                log::warn!("{path:?} not within {extra_path_buf:?}, skipping deletion!");
+                continue;
+            }
+            // files might be gone already as their parent directory got deleted before
+            let metadata = match std::fs::symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            };
+            // e.g. /run/user/<uid> is a tmpfs of its own, which gets removed by logind
+            if metadata.dev() != root_dev {
+                log::warn!("{path:?} is on another file system, skipping deletion!");
+                continue;
+            }
+
+            log::info!("Removing {path:?} of user {}", user.user_name);
+            let result = if metadata.is_dir() {
+                crate::secure_fs::remove_dir_all(&path)
+            } else {
+                std::fs::remove_file(&path)
+                    .map(|()| Vec::new())
+                    .map_err(anyhow::Error::from)
+            };
+            // keep on cleaning up other files, a single failure should not leave everything else behind
+            match result {
+                Ok(skipped) => {
+                    for mount_point in skipped {
+                        log::warn!(
+                            "Left mount point {mount_point:?} of user {} in place",
+                            user.user_name
+                        );
+                    }
+                }
+                Err(err) => log::warn!(
+                    "Unable to remove {path:?} of user {}: {err:?}",
+                    user.user_name
+                ),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Cleans up everything a guest user left behind after its last session ended:
+/// its home directory (see `cleanup_user_home`), its files within `cleanup_extra_paths` and its resource limits.
+/// Returns whether the home directory got removed.
+pub fn cleanup_user(settings: &Config, user: &crate::db::models::User) -> anyhow::Result<bool> {
+    let home_removed = cleanup_user_home(settings, user)?;
+    cleanup_user_files(settings, user)?;
+    remove_resource_limits(user)?;
+    Ok(home_removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the problems reported by `Config::validate`
+    fn problems(config: &Config) -> Vec<String> {
+        match config.validate() {
+            Ok(()) => Vec::new(),
+            Err(err) => err
+                .to_string()
+                .lines()
+                .skip(1)
+                .map(|line| line.trim_start_matches("  - ").to_string())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn parses_config_enums() {
+        let config: Config = toml::from_str(
+            "home_backend = \"tmpfs\"\nnetwork_policy = \"deny-lan\"\nid_recycle_leftover_policy = \"refuse\"",
+        )
+        .unwrap();
+        assert_eq!(config.home_backend, HomeBackend::Tmpfs);
+        assert_eq!(config.network_policy, NetworkPolicy::DenyLan);
+        assert_eq!(config.id_recycle_leftover_policy.to_string(), "refuse");
+
+        let err = toml::from_str::<Config>("network_policy = \"deny_lan\"").unwrap_err();
+        assert!(err.to_string().contains("deny-lan"));
+    }
+
+    #[test]
+    fn accepts_default_config() {
+        assert_eq!(problems(&Config::default()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_all_problems_at_once() {
+        let config = Config {
+            uid_minimum: 0,
+            enable_ghost_user: false,
+            gid_minimum: 32000,
+            gid_maximum: 31000,
+            ..Config::default()
+        };
+
+        assert_eq!(
+            problems(&config),
+            vec![
+                "uid_minimum must not be 0 (root)",
+                "gid_minimum (32000) must not be greater than gid_maximum (31000)",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_ghost_user_within_guest_range() {
+        let config = Config {
+            ghost_user_uid: 31500,
+            ..Config::default()
+        };
+        assert_eq!(problems(&config).len(), 1);
+
+        let config = Config {
+            ghost_user_uid: 31500,
+            enable_ghost_user: false,
+            ..Config::default()
+        };
+        assert!(problems(&config).is_empty());
+    }
+
+    #[test]
+    fn rejects_relative_paths() {
+        let config = Config {
+            home_base_path: "home/guests".to_string(),
+            cleanup_extra_paths: vec!["/tmp".to_string(), "tmp".to_string()],
+            ..Config::default()
+        };
+
+        assert_eq!(
+            problems(&config),
+            vec![
+                "home_base_path 'home/guests' has to be an absolute path",
+                "cleanup_extra_paths 'tmp' has to be an absolute path",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_scan_paths() {
+        let config = Config {
+            id_recycle_scan_paths: vec!["/".to_string(), "/home".to_string()],
+            guest_account_retention_scan_paths: vec!["var".to_string()],
+            ..Config::default()
+        };
+
+        assert_eq!(
+            problems(&config),
+            vec![
+                "id_recycle_scan_paths must not contain /",
+                "guest_account_retention_scan_paths 'var' has to be an absolute path",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_home_skel_templates_outside_of_home() {
+        let config = Config {
+            home_skel_templates: vec![
+                ".config/gtk-3.0/bookmarks".to_string(),
+                "/etc/passwd".to_string(),
+                "../other/.bashrc".to_string(),
+                "".to_string(),
+            ],
+            ..Config::default()
+        };
+
+        assert_eq!(problems(&config).len(), 3);
+    }
+
+    #[test]
+    fn checks_unlock_pin_length() {
+        for (length, valid) in [(0, false), (1, true), (32, true), (33, false)] {
+            let config = Config {
+                guest_unlock_pin: UnlockPinMode::Generate,
+                guest_unlock_pin_length: length,
+                ..Config::default()
+            };
+            assert_eq!(problems(&config).is_empty(), valid, "length {length}");
+        }
+
+        // the length does not matter as long as PINs are disabled
+        let config = Config {
+            guest_unlock_pin: UnlockPinMode::Off,
+            guest_unlock_pin_length: 0,
+            ..Config::default()
+        };
+        assert!(problems(&config).is_empty());
+    }
+
+    #[test]
+    fn checks_resource_limit_formats() {
+        for (cpu_quota, valid) in [
+            ("200%", true),
+            ("12.5%", true),
+            ("0%", false),
+            ("200", false),
+            ("%", false),
+            ("two%", false),
+        ] {
+            let config = Config {
+                guest_cpu_quota: cpu_quota.to_string(),
+                ..Config::default()
+            };
+            assert_eq!(problems(&config).is_empty(), valid, "{cpu_quota}");
+        }
+
+        for (memory_max, valid) in [
+            ("4G", true),
+            ("512M", true),
+            ("1073741824", true),
+            ("50%", true),
+            ("infinity", true),
+            ("G", false),
+            ("4GB", false),
+            ("-1G", false),
+            ("4 G", false),
+            ("0", false),
+            ("0G", false),
+        ] {
+            let config = Config {
+                guest_memory_max: memory_max.to_string(),
+                ..Config::default()
+            };
+            assert_eq!(problems(&config).is_empty(), valid, "{memory_max}");
+        }
+
+        for (tmpfs_size, valid) in [
+            ("1G", true),
+            ("10%", true),
+            ("0", false),
+            ("infinity", false),
+            ("12.5%", false),
+            ("1G,exec,suid", false),
+            ("", false),
+        ] {
+            let config = Config {
+                home_tmpfs_size: tmpfs_size.to_string(),
+                ..Config::default()
+            };
+            assert_eq!(problems(&config).is_empty(), valid, "{tmpfs_size}");
+
+            let config = Config {
+                guest_private_tmp_size: tmpfs_size.to_string(),
+                ..Config::default()
+            };
+            // an empty size uses the tmpfs default
+            assert_eq!(
+                problems(&config).is_empty(),
+                valid || tmpfs_size.is_empty(),
+                "{tmpfs_size}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_negative_limits() {
+        let config = Config {
+            guest_home_quota_bytes: -1,
+            guest_session_max_duration: -1,
+            guest_idle_timeout: -1,
+            guest_tasks_max: -1,
+            guest_account_retention_days: -1,
+            cleanup_reconcile_interval: -1,
+            ..Config::default()
+        };
+        assert_eq!(problems(&config).len(), 6);
+    }
+}
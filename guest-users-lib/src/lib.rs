@@ -3,5 +3,10 @@ extern crate diesel;
 #[macro_use]
 extern crate anyhow;
 
+pub mod daemon_socket;
 pub mod db;
 pub mod helper;
+pub mod logind;
+pub mod naming;
+pub mod network_policy;
+pub mod secure_fs;
@@ -0,0 +1,112 @@
+//! D-Bus bindings to systemd-logind
+
+/// Session as listed by logind: (session ID, UID, user name, seat ID, object path)
+pub type SessionEntry = (String, u32, String, String, zbus::zvariant::OwnedObjectPath);
+
+/// Session state of sessions, which got logged out but still have processes left
+const SESSION_STATE_CLOSING: &str = "closing";
+
+#[zbus::proxy(
+    interface = "org.freedesktop.login1.Manager",
+    default_service = "org.freedesktop.login1",
+    default_path = "/org/freedesktop/login1"
+)]
+pub trait LoginManager {
+    /// ListSessions method
+    fn list_sessions(&self) -> zbus::Result<Vec<SessionEntry>>;
+
+    /// TerminateSession method
+    fn terminate_session(&self, session_id: &str) -> zbus::Result<()>;
+
+    /// TerminateUser method
+    fn terminate_user(&self, uid: u32) -> zbus::Result<()>;
+
+    /// SetUserLinger method
+    fn set_user_linger(&self, uid: u32, enable: bool, interactive: bool) -> zbus::Result<()>;
+
+    /// SessionNew signal
+    #[zbus(signal)]
+    fn session_new(
+        &self,
+        session_id: &str,
+        object_path: zbus::zvariant::ObjectPath<'_>,
+    ) -> zbus::Result<()>;
+
+    /// SessionRemoved signal
+    #[zbus(signal)]
+    fn session_removed(
+        &self,
+        session_id: &str,
+        object_path: zbus::zvariant::ObjectPath<'_>,
+    ) -> zbus::Result<()>;
+}
+
+#[zbus::proxy(
+    interface = "org.freedesktop.login1.Session",
+    default_service = "org.freedesktop.login1"
+)]
+pub trait LoginSession {
+    /// Name property (user name)
+    #[zbus(property)]
+    fn name(&self) -> zbus::Result<String>;
+
+    /// State property (online, active or closing)
+    #[zbus(property)]
+    fn state(&self) -> zbus::Result<String>;
+
+    /// IdleHint property
+    #[zbus(property)]
+    fn idle_hint(&self) -> zbus::Result<bool>;
+
+    /// IdleSinceHint property (CLOCK_REALTIME in microseconds)
+    #[zbus(property)]
+    fn idle_since_hint(&self) -> zbus::Result<u64>;
+}
+
+/// Returns whether a user has sessions, which are not closing yet.
+/// Must not be called from within an async runtime, use `has_active_user_sessions` there.
+pub fn has_active_user_sessions_blocking(user_name: &str) -> anyhow::Result<bool> {
+    let system_connection = zbus::blocking::Connection::system()?;
+    let login_interface = LoginManagerProxyBlocking::new(&system_connection)?;
+
+    for (session_id, _, session_user_name, _, session_path) in login_interface.list_sessions()? {
+        if session_user_name != user_name {
+            continue;
+        }
+        let session = LoginSessionProxyBlocking::builder(&system_connection)
+            .path(session_path)?
+            .build()?;
+        let state = session.state()?;
+        log::trace!("Session {session_id} of user {user_name} is {state}");
+        if state != SESSION_STATE_CLOSING {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Returns whether a user has sessions, which are not closing yet
+pub async fn has_active_user_sessions(
+    system_connection: &zbus::Connection,
+    user_name: &str,
+) -> anyhow::Result<bool> {
+    let login_interface = LoginManagerProxy::new(system_connection).await?;
+
+    for (session_id, _, session_user_name, _, session_path) in
+        login_interface.list_sessions().await?
+    {
+        if session_user_name != user_name {
+            continue;
+        }
+        let session = LoginSessionProxy::builder(system_connection)
+            .path(session_path)?
+            .build()
+            .await?;
+        let state = session.state().await?;
+        log::trace!("Session {session_id} of user {user_name} is {state}");
+        if state != SESSION_STATE_CLOSING {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
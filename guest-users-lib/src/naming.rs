@@ -0,0 +1,319 @@
+//! Guest user names and human readable names rendered from templates like `{prefix}-{uid}` or `{adjective}-{animal}`
+
+use crate::helper::Config;
+
+/// Placeholders available within name templates
+const PLACEHOLDERS: &[&str] = &[
+    "prefix",
+    "uid",
+    "seq",
+    "date",
+    "random",
+    "adjective",
+    "animal",
+];
+
+/// Placeholders of which at least one has to be part of `guest_username_template`, so user names differ
+const DISTINCT_PLACEHOLDERS: &[&str] = &["uid", "seq", "random", "adjective", "animal"];
+
+const ADJECTIVES: &[&str] = &[
+    "amber", "brave", "bright", "calm", "clever", "cosmic", "crisp", "curious", "daring", "eager",
+    "fancy", "gentle", "happy", "jolly", "keen", "kind", "lively", "lucky", "merry", "mighty",
+    "nimble", "noble", "plucky", "proud", "quick", "quiet", "rapid", "shiny", "snappy", "sunny",
+    "swift", "witty",
+];
+
+const ANIMALS: &[&str] = &[
+    "badger", "beaver", "bison", "crane", "dolphin", "falcon", "ferret", "gecko", "heron", "ibex",
+    "jaguar", "koala", "lemur", "lynx", "marten", "moose", "narwhal", "ocelot", "otter", "owl",
+    "panda", "puffin", "quokka", "rabbit", "raven", "seal", "sloth", "tapir", "toucan", "walrus",
+    "wombat", "yak",
+];
+
+const RANDOM_CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789";
+const RANDOM_LENGTH: usize = 6;
+
+/// Maximum length of user names supported by common tools like `useradd`
+const USER_NAME_MAX_LENGTH: usize = 32;
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Names of a new guest user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuestNames {
+    pub user_name: String,
+    pub human_name: String,
+}
+
+/// Values substituted for placeholders, shared by the user name and the human readable name so both match
+struct Values {
+    uid: i64,
+    seq: i64,
+    date: String,
+    random: String,
+    adjective: &'static str,
+    animal: &'static str,
+}
+
+impl Values {
+    fn random(settings: &Config, uid: i64, created_at: chrono::NaiveDateTime) -> Self {
+        use rand::seq::SliceRandom;
+        use rand::Rng;
+
+        let mut rng = rand::rngs::OsRng;
+        Values {
+            uid,
+            seq: uid - i64::from(settings.uid_minimum) + 1,
+            date: created_at.format(DATE_FORMAT).to_string(),
+            random: (0..RANDOM_LENGTH)
+                .map(|_| char::from(RANDOM_CHARS[rng.gen_range(0..RANDOM_CHARS.len())]))
+                .collect(),
+            adjective: ADJECTIVES.choose(&mut rng).unwrap(),
+            animal: ANIMALS.choose(&mut rng).unwrap(),
+        }
+    }
+
+    /// Values resulting in the longest possible names
+    fn longest(settings: &Config) -> Self {
+        let longest = |words: &[&'static str]| *words.iter().max_by_key(|word| word.len()).unwrap();
+        Values {
+            uid: settings.uid_maximum.into(),
+            seq: i64::from(settings.uid_maximum) - i64::from(settings.uid_minimum) + 1,
+            date: chrono::Utc::now().format(DATE_FORMAT).to_string(),
+            random: "z".repeat(RANDOM_LENGTH),
+            adjective: longest(ADJECTIVES),
+            animal: longest(ANIMALS),
+        }
+    }
+}
+
+/// Substitutes all placeholders of a template, fails on unknown placeholders or unbalanced braces
+fn render(template: &str, prefix: &str, values: &Values) -> anyhow::Result<String> {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        rendered.push_str(&rest[..start]);
+        if rest[start..].starts_with('}') {
+            bail!("Unmatched '}}' in name template '{template}'");
+        }
+        let end = match rest[start..].find('}') {
+            Some(length) => start + length,
+            None => bail!("Unclosed '{{' in name template '{template}'"),
+        };
+        let placeholder = &rest[start + 1..end];
+        match placeholder {
+            "prefix" => rendered.push_str(prefix),
+            "uid" => rendered.push_str(&values.uid.to_string()),
+            "seq" => rendered.push_str(&values.seq.to_string()),
+            "date" => rendered.push_str(&values.date),
+            "random" => rendered.push_str(&values.random),
+            "adjective" => rendered.push_str(values.adjective),
+            "animal" => rendered.push_str(values.animal),
+            _ => bail!(
+                "Unknown placeholder '{{{placeholder}}}' in name template '{template}', available placeholders: [{}]",
+                PLACEHOLDERS.join(", ")
+            ),
+        }
+        rest = &rest[end + 1..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// Returns whether a user name is portable (lower case letters, digits, `_` and `-`, not starting with a digit or `-`)
+fn is_valid_user_name(user_name: &str) -> bool {
+    let mut chars = user_name.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_lowercase() || first == '_' => {}
+        _ => return false,
+    }
+    user_name.len() <= USER_NAME_MAX_LENGTH
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+}
+
+/// Returns whether a human readable name can be stored in the GECOS field of the passwd database
+fn is_valid_human_name(human_name: &str) -> bool {
+    !human_name.is_empty() && !human_name.chars().any(|c| c == ':' || c.is_control())
+}
+
+fn render_names(settings: &Config, values: &Values) -> anyhow::Result<GuestNames> {
+    Ok(GuestNames {
+        user_name: render(
+            &settings.guest_username_template,
+            &settings.guest_username_prefix,
+            values,
+        )?,
+        human_name: render(
+            &settings.guest_human_name_template,
+            &settings.guest_username_human_readable_prefix,
+            values,
+        )?,
+    })
+}
+
+/// Renders the names of a new guest user with the given UID
+pub fn generate(
+    settings: &Config,
+    uid: i64,
+    created_at: chrono::NaiveDateTime,
+) -> anyhow::Result<GuestNames> {
+    let names = render_names(settings, &Values::random(settings, uid, created_at))?;
+    if !is_valid_user_name(&names.user_name) {
+        bail!("Generated user name '{}' is invalid", names.user_name);
+    }
+    if !is_valid_human_name(&names.human_name) {
+        bail!(
+            "Generated human readable name '{}' is invalid",
+            names.human_name
+        );
+    }
+    Ok(names)
+}
+
+/// Checks `guest_username_template` and `guest_human_name_template` for unknown placeholders
+/// and makes sure they render valid names for the whole guest UID range
+pub fn validate_templates(settings: &Config) -> anyhow::Result<()> {
+    let names = render_names(settings, &Values::longest(settings))?;
+
+    if !DISTINCT_PLACEHOLDERS.iter().any(|placeholder| {
+        settings
+            .guest_username_template
+            .contains(&format!("{{{placeholder}}}"))
+    }) {
+        bail!(
+            "guest_username_template '{}' has to contain at least one of the placeholders [{}], so guest users get distinct names",
+            settings.guest_username_template,
+            DISTINCT_PLACEHOLDERS
+                .iter()
+                .map(|placeholder| format!("{{{placeholder}}}"))
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+    }
+    if !is_valid_user_name(&names.user_name) {
+        bail!(
+            "guest_username_template '{}' renders invalid user names like '{}'. User names may only contain lower case letters, digits, '_' and '-', must start with a letter or '_' and must not exceed {USER_NAME_MAX_LENGTH} characters.",
+            settings.guest_username_template,
+            names.user_name
+        );
+    }
+    if !is_valid_human_name(&names.human_name) {
+        bail!(
+            "guest_human_name_template '{}' renders invalid names like '{}'. Names must not be empty and must not contain ':' or control characters.",
+            settings.guest_human_name_template,
+            names.human_name
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_values() -> Values {
+        Values {
+            uid: 31005,
+            seq: 5,
+            date: "2024-05-17".to_string(),
+            random: "a1b2c3".to_string(),
+            adjective: "brave",
+            animal: "otter",
+        }
+    }
+
+    fn test_settings(username_template: &str, human_name_template: &str) -> Config {
+        Config {
+            guest_username_template: username_template.to_string(),
+            guest_human_name_template: human_name_template.to_string(),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn renders_each_placeholder() {
+        let values = test_values();
+        for (template, expected) in [
+            ("{prefix}", "guest"),
+            ("{uid}", "31005"),
+            ("{seq}", "5"),
+            ("{date}", "2024-05-17"),
+            ("{random}", "a1b2c3"),
+            ("{adjective}", "brave"),
+            ("{animal}", "otter"),
+            ("x-{adjective}-{animal}-y", "x-brave-otter-y"),
+            ("{prefix} {uid} (#{uid})", "guest 31005 (#31005)"),
+        ] {
+            assert_eq!(render(template, "guest", &values).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_placeholders() {
+        let err = render("{prefix}-{name}", "guest", &test_values()).unwrap_err();
+        assert!(err.to_string().contains("Unknown placeholder '{name}'"));
+
+        assert!(validate_templates(&test_settings("{prefix}-{uid}-{name}", "{prefix}")).is_err());
+        assert!(validate_templates(&test_settings("{prefix}-{uid}", "{prefix} {name}")).is_err());
+    }
+
+    #[test]
+    fn rejects_unbalanced_braces() {
+        assert!(render("{prefix-{uid}", "guest", &test_values()).is_err());
+        assert!(render("{prefix", "guest", &test_values()).is_err());
+        assert!(render("prefix}-{uid}", "guest", &test_values()).is_err());
+    }
+
+    #[test]
+    fn rejects_templates_colliding_across_uids() {
+        // neither the prefix nor the date differ between guest users created on the same day
+        let err = validate_templates(&test_settings("{prefix}-{date}", "{prefix}")).unwrap_err();
+        assert!(err.to_string().contains("distinct names"));
+
+        let created_at = chrono::NaiveDate::from_ymd_opt(2024, 5, 17)
+            .unwrap()
+            .and_hms_opt(14, 32, 0)
+            .unwrap();
+        let settings = test_settings("{prefix}-{seq}", "{prefix} {seq}");
+        validate_templates(&settings).unwrap();
+        assert_ne!(
+            generate(&settings, 31001, created_at).unwrap(),
+            generate(&settings, 31002, created_at).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_user_names() {
+        // upper case letters
+        assert!(validate_templates(&test_settings("Guest-{uid}", "{prefix}")).is_err());
+        // starting with a digit
+        assert!(validate_templates(&test_settings("{uid}-{prefix}", "{prefix}")).is_err());
+        // too long for the whole UID range
+        assert!(validate_templates(&test_settings(
+            "{prefix}-{adjective}-{animal}-{date}-{uid}",
+            "{prefix}"
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_human_names() {
+        assert!(validate_templates(&test_settings("{prefix}-{uid}", "{prefix}: {uid}")).is_err());
+        assert!(validate_templates(&test_settings("{prefix}-{uid}", "")).is_err());
+    }
+
+    #[test]
+    fn accepts_default_templates() {
+        let settings = Config::default();
+        validate_templates(&settings).unwrap();
+
+        let created_at = chrono::Utc::now().naive_utc();
+        assert_eq!(
+            generate(&settings, 31005, created_at).unwrap(),
+            GuestNames {
+                user_name: "guest-31005".to_string(),
+                human_name: "Guest (31005)".to_string(),
+            }
+        );
+    }
+}
@@ -0,0 +1,331 @@
+//! Network access policy for guest users, enforced by nftables rules matching the guest UID range (`meta skuid`)
+
+use std::io::Write;
+
+use anyhow::Context;
+
+use crate::helper::Config;
+
+/// nftables table containing all guest users rules
+const NFT_TABLE: &str = "inet guest_users";
+
+/// nftables chain containing the rules applied to packets of guest users
+const NFT_POLICY_CHAIN: &str = "guest_policy";
+
+const NFT_PATH: &str = "/usr/sbin/nft";
+
+/// Private, link-local and shared address ranges considered as LAN
+const LAN_NETWORKS_IPV4: &[&str] = &[
+    "10.0.0.0/8",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "169.254.0.0/16",
+    "100.64.0.0/10",
+];
+const LAN_NETWORKS_IPV6: &[&str] = &["fc00::/7", "fe80::/10"];
+
+crate::helper::config_enum!(
+    /// Network access of guest users
+    pub enum NetworkPolicy {
+        /// No restrictions (besides `network_deny`)
+        Open => "open",
+        /// No access to private networks (besides `network_allow`)
+        DenyLan => "deny-lan",
+        /// Only access to the HTTP(S) proxy `network_proxy` (besides `network_allow`)
+        ProxyOnly => "proxy-only",
+    }
+);
+
+/// Addresses or networks (CIDR notation) split by IP version
+#[derive(Default)]
+struct Networks {
+    ipv4: Vec<String>,
+    ipv6: Vec<String>,
+}
+
+impl Networks {
+    /// Parses addresses or networks like `192.168.1.10` or `fd00::/8`
+    fn parse(networks: &[String]) -> anyhow::Result<Self> {
+        let mut parsed = Networks::default();
+        for network in networks {
+            let (address, prefix_length) = match network.split_once('/') {
+                Some((address, prefix_length)) => (address, Some(prefix_length)),
+                None => (network.as_str(), None),
+            };
+            let address = address
+                .parse::<std::net::IpAddr>()
+                .with_context(|| format!("Invalid network address '{network}'"))?;
+            let max_prefix_length = if address.is_ipv4() { 32 } else { 128 };
+            if let Some(prefix_length) = prefix_length {
+                let prefix_length = match prefix_length.parse::<u8>() {
+                    Ok(prefix_length) if prefix_length <= max_prefix_length => prefix_length,
+                    _ => bail!("Invalid prefix length in network '{network}'"),
+                };
+                // nft refuses networks like 10.0.0.1/8, which most likely are a typo anyway
+                let host_bits = u32::from(max_prefix_length - prefix_length);
+                let has_host_bits = match address {
+                    std::net::IpAddr::V4(address) => {
+                        u32::from(address) & !u32::MAX.checked_shl(host_bits).unwrap_or(0) != 0
+                    }
+                    std::net::IpAddr::V6(address) => {
+                        u128::from(address) & !u128::MAX.checked_shl(host_bits).unwrap_or(0) != 0
+                    }
+                };
+                if has_host_bits {
+                    bail!("Network '{network}' has host bits set");
+                }
+            }
+            if address.is_ipv4() {
+                parsed.ipv4.push(network.clone());
+            } else {
+                parsed.ipv6.push(network.clone());
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Appends rules applying the verdict to all networks
+    fn push_rules(&self, rules: &mut Vec<String>, verdict: &str) {
+        if !self.ipv4.is_empty() {
+            rules.push(format!("ip daddr {{ {} }} {verdict}", self.ipv4.join(", ")));
+        }
+        if !self.ipv6.is_empty() {
+            rules.push(format!(
+                "ip6 daddr {{ {} }} {verdict}",
+                self.ipv6.join(", ")
+            ));
+        }
+    }
+}
+
+/// Generates the nftables ruleset enforcing the configured `network_policy` for the guest UID range.
+/// The ruleset replaces any previously applied guest users ruleset, so it can be applied repeatedly.
+pub fn generate_ruleset(settings: &Config) -> anyhow::Result<String> {
+    let allow = Networks::parse(&settings.network_allow).context("Invalid network_allow")?;
+    let deny = Networks::parse(&settings.network_deny).context("Invalid network_deny")?;
+
+    // creating the table before deleting it makes sure deleting does not fail if it does not exist yet
+    let mut ruleset = format!("table {NFT_TABLE}\ndelete table {NFT_TABLE}\n");
+    if settings.network_policy == NetworkPolicy::Open
+        && deny.ipv4.is_empty()
+        && deny.ipv6.is_empty()
+    {
+        return Ok(ruleset);
+    }
+
+    let mut rules = vec![
+        // local services (like the DNS stub resolver or the print server) stay reachable
+        "oif \"lo\" accept".to_string(),
+        "ct state established,related accept".to_string(),
+    ];
+    deny.push_rules(&mut rules, "reject");
+    allow.push_rules(&mut rules, "accept");
+    match settings.network_policy {
+        NetworkPolicy::Open => {}
+        NetworkPolicy::DenyLan => {
+            let lan = Networks {
+                ipv4: LAN_NETWORKS_IPV4.iter().map(|s| s.to_string()).collect(),
+                ipv6: LAN_NETWORKS_IPV6.iter().map(|s| s.to_string()).collect(),
+            };
+            lan.push_rules(&mut rules, "reject");
+        }
+        NetworkPolicy::ProxyOnly => {
+            let proxy = settings
+                .network_proxy
+                .parse::<std::net::SocketAddr>()
+                .with_context(|| {
+                    format!(
+                        "Invalid network_proxy '{}', expected e.g. 10.0.0.1:3128",
+                        settings.network_proxy
+                    )
+                })?;
+            let address_family = if proxy.is_ipv4() { "ip" } else { "ip6" };
+            rules.push(format!(
+                "{address_family} daddr {} tcp dport {} accept",
+                proxy.ip(),
+                proxy.port()
+            ));
+            rules.push("reject".to_string());
+        }
+    }
+
+    // only packets of guest sockets enter the policy chain, anything else (including packets without a socket) passes
+    ruleset.push_str(&format!(
+        "table {NFT_TABLE} {{\n    chain output {{\n        type filter hook output priority 0; policy accept;\n        meta skuid {}-{} jump {NFT_POLICY_CHAIN}\n    }}\n    chain {NFT_POLICY_CHAIN} {{\n{}    }}\n}}\n",
+        settings.uid_minimum,
+        settings.uid_maximum,
+        rules
+            .iter()
+            .map(|rule| format!("        {rule}\n"))
+            .collect::<String>()
+    ));
+    Ok(ruleset)
+}
+
+/// Passes a ruleset to nftables, which applies it atomically
+fn run_nft(ruleset: &str) -> anyhow::Result<()> {
+    let mut nft = std::process::Command::new(NFT_PATH)
+        .args(["-f", "-"])
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Unable to run {NFT_PATH}"))?;
+    nft.stdin
+        .take()
+        .context("Unable to open stdin of nft")?
+        .write_all(ruleset.as_bytes())?;
+    let status = nft.wait()?;
+    if !status.success() {
+        bail!("{NFT_PATH} failed with {status}");
+    }
+    Ok(())
+}
+
+/// Applies the configured network policy
+pub fn apply(settings: &Config) -> anyhow::Result<()> {
+    let ruleset = generate_ruleset(settings)?;
+    log::info!("Applying network policy {}", settings.network_policy);
+    run_nft(&ruleset)
+}
+
+/// Removes all guest users rules, so guest users get unrestricted network access
+pub fn flush() -> anyhow::Result<()> {
+    log::info!("Flushing network policy");
+    run_nft(&format!("table {NFT_TABLE}\ndelete table {NFT_TABLE}\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_settings(network_policy: NetworkPolicy) -> Config {
+        Config {
+            uid_minimum: 31000,
+            uid_maximum: 31999,
+            network_policy,
+            ..Config::default()
+        }
+    }
+
+    /// Returns the rules of the guest policy chain
+    fn policy_rules(ruleset: &str) -> Vec<&str> {
+        let chain = ruleset
+            .split_once(&format!("chain {NFT_POLICY_CHAIN} {{\n"))
+            .expect("policy chain missing")
+            .1;
+        chain
+            .lines()
+            .map(str::trim)
+            .take_while(|line| *line != "}")
+            .collect()
+    }
+
+    #[test]
+    fn open_without_deny_list_only_removes_rules() {
+        let ruleset = generate_ruleset(&test_settings(NetworkPolicy::Open)).unwrap();
+
+        assert_eq!(
+            ruleset,
+            "table inet guest_users\ndelete table inet guest_users\n"
+        );
+    }
+
+    #[test]
+    fn guest_uid_range_jumps_to_policy_chain() {
+        let ruleset = generate_ruleset(&test_settings(NetworkPolicy::DenyLan)).unwrap();
+
+        assert!(ruleset.contains(
+            "type filter hook output priority 0; policy accept;\n        meta skuid 31000-31999 jump guest_policy\n    }"
+        ));
+        assert!(!ruleset.contains("skuid !="));
+    }
+
+    #[test]
+    fn deny_list_rejects_denied_networks() {
+        let mut settings = test_settings(NetworkPolicy::Open);
+        settings.network_deny = vec!["192.168.1.0/24".to_string(), "fd00::1".to_string()];
+        let ruleset = generate_ruleset(&settings).unwrap();
+
+        assert_eq!(
+            policy_rules(&ruleset),
+            vec![
+                "oif \"lo\" accept",
+                "ct state established,related accept",
+                "ip daddr { 192.168.1.0/24 } reject",
+                "ip6 daddr { fd00::1 } reject",
+            ]
+        );
+    }
+
+    #[test]
+    fn allow_list_takes_precedence_over_lan_rejects() {
+        let mut settings = test_settings(NetworkPolicy::DenyLan);
+        settings.network_allow = vec!["192.168.1.10".to_string()];
+        let ruleset = generate_ruleset(&settings).unwrap();
+
+        assert_eq!(
+            policy_rules(&ruleset),
+            vec![
+                "oif \"lo\" accept",
+                "ct state established,related accept",
+                "ip daddr { 192.168.1.10 } accept",
+                "ip daddr { 10.0.0.0/8, 172.16.0.0/12, 192.168.0.0/16, 169.254.0.0/16, 100.64.0.0/10 } reject",
+                "ip6 daddr { fc00::/7, fe80::/10 } reject",
+            ]
+        );
+    }
+
+    #[test]
+    fn proxy_only_rejects_everything_but_the_proxy() {
+        let mut settings = test_settings(NetworkPolicy::ProxyOnly);
+        settings.network_proxy = "10.0.0.1:3128".to_string();
+        settings.network_allow = vec!["10.0.0.53".to_string()];
+        let ruleset = generate_ruleset(&settings).unwrap();
+
+        assert_eq!(
+            policy_rules(&ruleset),
+            vec![
+                "oif \"lo\" accept",
+                "ct state established,related accept",
+                "ip daddr { 10.0.0.53 } accept",
+                "ip daddr 10.0.0.1 tcp dport 3128 accept",
+                "reject",
+            ]
+        );
+    }
+
+    #[test]
+    fn proxy_only_requires_valid_proxy() {
+        let mut settings = test_settings(NetworkPolicy::ProxyOnly);
+        settings.network_proxy = "proxy.example.org".to_string();
+
+        assert!(generate_ruleset(&settings).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_networks() {
+        let mut settings = test_settings(NetworkPolicy::DenyLan);
+        settings.network_allow = vec!["10.0.0.0/33".to_string()];
+        assert!(generate_ruleset(&settings).is_err());
+
+        settings.network_allow = vec!["not-an-address".to_string()];
+        assert!(generate_ruleset(&settings).is_err());
+    }
+
+    #[test]
+    fn rejects_networks_with_host_bits() {
+        let mut settings = test_settings(NetworkPolicy::DenyLan);
+        for network in ["10.0.0.1/8", "fd00::1/8"] {
+            settings.network_allow = vec![network.to_string()];
+            assert!(generate_ruleset(&settings).is_err(), "{}", network);
+        }
+
+        settings.network_allow = vec![
+            "10.0.0.0/8".to_string(),
+            "10.0.0.1/32".to_string(),
+            "0.0.0.0/0".to_string(),
+            "fd00::/8".to_string(),
+            "::/0".to_string(),
+        ];
+        assert!(generate_ruleset(&settings).is_ok());
+    }
+}
@@ -0,0 +1,827 @@
+//! File system operations on directories controlled by guest users.
+//! Guest users might place symlinks or (bind) mounts within their directories, so path based operations like
+//! `std::fs::remove_dir_all` could be tricked into touching files outside of them.
+//! Therefore, all operations work relative to opened directories (`openat` and friends) without following symlinks.
+
+use std::convert::TryFrom;
+use std::ffi::{c_void, CStr, CString, OsStr};
+use std::os::raw::c_char;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use nix::dir::Dir;
+use nix::fcntl::{AtFlags, OFlag};
+use nix::sys::stat::{Mode, SFlag};
+use nix::unistd::UnlinkatFlags;
+
+const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+
+/// Maximum directory depth handled when copying, as every level keeps a file descriptor open
+const MAX_DEPTH: usize = 512;
+
+/// Number of innermost directories kept open while removing, outer ones get reopened once needed again
+const MAX_OPEN_DIRS: usize = 64;
+
+/// Flags for opening directories without following symlinks
+fn dir_open_flags() -> OFlag {
+    OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC
+}
+
+/// Unescapes a mountinfo field, which uses octal escapes for spaces and other special characters (e.g. `\040`)
+fn unescape_mountinfo_field(field: &str) -> PathBuf {
+    let bytes = field.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 4 <= bytes.len() {
+            if let Ok(byte) = u8::from_str_radix(&field[i + 1..i + 4], 8) {
+                unescaped.push(byte);
+                i += 4;
+                continue;
+            }
+        }
+        unescaped.push(bytes[i]);
+        i += 1;
+    }
+    PathBuf::from(OsStr::from_bytes(&unescaped))
+}
+
+/// Returns the mount points listed in the content of a mountinfo file (see proc(5))
+fn parse_mountinfo(mountinfo: &str) -> Vec<PathBuf> {
+    mountinfo
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(4))
+        .map(unescape_mountinfo_field)
+        .collect()
+}
+
+/// Returns all mount points located below the given path (excluding the path itself)
+pub fn mount_points_below(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mountinfo = std::fs::read_to_string(MOUNTINFO_PATH)
+        .with_context(|| format!("Unable to read {MOUNTINFO_PATH}"))?;
+    Ok(parse_mountinfo(&mountinfo)
+        .into_iter()
+        .filter(|mount_point| mount_point != path && mount_point.starts_with(path))
+        .collect())
+}
+
+/// Removes a directory recursively without following symlinks and without leaving its file system.
+/// Mount points found within the directory (e.g. FUSE mounts of guest users) are left in place,
+/// along with the directories containing them. Returns the skipped mount points.
+pub fn remove_dir_all(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mount_points = mount_points_below(path)?;
+    remove_dir_all_except(path, &mount_points)
+}
+
+fn remove_dir_all_except(path: &Path, mount_points: &[PathBuf]) -> anyhow::Result<Vec<PathBuf>> {
+    let dir = Dir::open(path, dir_open_flags(), Mode::empty())
+        .with_context(|| format!("Unable to open directory {path:?}"))?;
+    let dev = nix::sys::stat::fstat(dir.as_raw_fd())?.st_dev;
+
+    let mut skipped = Vec::new();
+    remove_dir_contents(dir, path, dev, mount_points, &mut skipped)?;
+
+    // the directory cannot be removed while mount points are left within it
+    if skipped.is_empty() {
+        nix::unistd::unlinkat(None, path, UnlinkatFlags::RemoveDir)
+            .with_context(|| format!("Unable to remove directory {path:?}"))?;
+    }
+    Ok(skipped)
+}
+
+/// Directory on the stack of `remove_dir_contents`
+struct PendingDir {
+    /// Name within the parent directory
+    name: CString,
+    path: PathBuf,
+    /// Entries not removed yet
+    names: Vec<CString>,
+    /// `None` if the directory got closed to limit the number of open file descriptors
+    dir: Option<Dir>,
+}
+
+/// Lists the entries of an opened directory (excluding `.` and `..`)
+fn list_dir(dir: &mut Dir, dir_path: &Path) -> anyhow::Result<Vec<CString>> {
+    // collect the entries first, as removing entries while iterating leads to undefined results
+    let mut names = Vec::new();
+    for entry in dir.iter() {
+        let entry = entry.with_context(|| format!("Unable to read directory {dir_path:?}"))?;
+        let name = entry.file_name().to_bytes();
+        if name != b"." && name != b".." {
+            names.push(CString::from(entry.file_name()));
+        }
+    }
+    Ok(names)
+}
+
+/// Opens a subdirectory without following symlinks, returns `None` if it is located on another file system
+fn open_child_dir(
+    dirfd: RawFd,
+    name: &CStr,
+    path: &Path,
+    dev: nix::libc::dev_t,
+) -> anyhow::Result<Option<Dir>> {
+    // O_NOFOLLOW makes sure the directory did not get replaced by a symlink in the meantime
+    let child = Dir::openat(Some(dirfd), name, dir_open_flags(), Mode::empty())
+        .with_context(|| format!("Unable to open directory {path:?}"))?;
+    if nix::sys::stat::fstat(child.as_raw_fd())?.st_dev != dev {
+        return Ok(None);
+    }
+    Ok(Some(child))
+}
+
+/// Reopens the directory at the given position of the stack (and its closed parents) starting from the innermost open one
+fn reopen_pending_dir(
+    stack: &mut [PendingDir],
+    index: usize,
+    dev: nix::libc::dev_t,
+) -> anyhow::Result<RawFd> {
+    // the outermost directory is never closed
+    let mut open = index;
+    while stack[open].dir.is_none() {
+        open -= 1;
+    }
+    for child in open + 1..=index {
+        let parent_fd = stack[child - 1].dir.as_ref().unwrap().as_raw_fd();
+        let dir = open_child_dir(parent_fd, &stack[child].name, &stack[child].path, dev)?
+            .with_context(|| format!("{:?} got mounted while being removed", stack[child].path))?;
+        stack[child].dir = Some(dir);
+    }
+    Ok(stack[index].dir.as_ref().unwrap().as_raw_fd())
+}
+
+/// Removes the contents of an opened directory, collecting all mount points, which got skipped.
+/// Directories get traversed depth first using a stack instead of recursion, which only keeps the innermost
+/// `MAX_OPEN_DIRS` directories open, so arbitrarily deep trees get removed without running out of file descriptors.
+fn remove_dir_contents(
+    mut dir: Dir,
+    dir_path: &Path,
+    dev: nix::libc::dev_t,
+    mount_points: &[PathBuf],
+    skipped: &mut Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    let names = list_dir(&mut dir, dir_path)?;
+    let mut stack = vec![PendingDir {
+        name: CString::default(),
+        path: dir_path.to_path_buf(),
+        names,
+        dir: Some(dir),
+    }];
+
+    while let Some(current) = stack.len().checked_sub(1) {
+        let name = match stack[current].names.pop() {
+            Some(name) => name,
+            None => {
+                // all entries are gone, so remove the directory itself (unless it is the outermost one)
+                let finished = stack.pop().unwrap();
+                let parent = match stack.len().checked_sub(1) {
+                    Some(parent) => parent,
+                    None => break,
+                };
+                // directories containing mount points cannot be removed
+                if skipped
+                    .iter()
+                    .any(|skipped_path| skipped_path.starts_with(&finished.path))
+                {
+                    continue;
+                }
+                let parent_fd = reopen_pending_dir(&mut stack, parent, dev)?;
+                nix::unistd::unlinkat(
+                    Some(parent_fd),
+                    finished.name.as_c_str(),
+                    UnlinkatFlags::RemoveDir,
+                )
+                .with_context(|| format!("Unable to remove directory {:?}", finished.path))?;
+                continue;
+            }
+        };
+
+        let dirfd = reopen_pending_dir(&mut stack, current, dev)?;
+        let entry_path = stack[current].path.join(OsStr::from_bytes(name.to_bytes()));
+        let stat = match nix::sys::stat::fstatat(
+            Some(dirfd),
+            name.as_c_str(),
+            AtFlags::AT_SYMLINK_NOFOLLOW,
+        ) {
+            Ok(stat) => stat,
+            Err(nix::errno::Errno::ENOENT) => continue,
+            Err(err) => return Err(err).with_context(|| format!("Unable to stat {entry_path:?}")),
+        };
+
+        // bind mounts of the same file system do not change the device, so check the mount table as well
+        if stat.st_dev != dev || mount_points.contains(&entry_path) {
+            log::warn!("{entry_path:?} is a mount point, skipping it!");
+            skipped.push(entry_path);
+            continue;
+        }
+
+        if SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT == SFlag::S_IFDIR {
+            let mut child = match open_child_dir(dirfd, name.as_c_str(), &entry_path, dev)? {
+                Some(child) => child,
+                None => {
+                    log::warn!("{entry_path:?} is a mount point, skipping it!");
+                    skipped.push(entry_path);
+                    continue;
+                }
+            };
+            let names = list_dir(&mut child, &entry_path)?;
+            stack.push(PendingDir {
+                name,
+                path: entry_path,
+                names,
+                dir: Some(child),
+            });
+            // keep the outermost directory open, every other one can be reopened from its parent
+            if stack.len() > MAX_OPEN_DIRS + 1 {
+                let close = stack.len() - MAX_OPEN_DIRS - 1;
+                stack[close].dir = None;
+            }
+        } else {
+            nix::unistd::unlinkat(Some(dirfd), name.as_c_str(), UnlinkatFlags::NoRemoveDir)
+                .with_context(|| format!("Unable to remove {entry_path:?}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Lists the extended attributes of an opened file, file systems without xattr support have none
+fn list_xattrs(fd: RawFd) -> anyhow::Result<Vec<CString>> {
+    let size = unsafe { nix::libc::flistxattr(fd, std::ptr::null_mut(), 0) };
+    if size < 0 {
+        let err = nix::errno::Errno::last();
+        if err == nix::errno::Errno::ENOTSUP {
+            return Ok(Vec::new());
+        }
+        return Err(err.into());
+    }
+    let mut names = vec![0u8; usize::try_from(size)?];
+    let size = unsafe { nix::libc::flistxattr(fd, names.as_mut_ptr() as *mut c_char, names.len()) };
+    if size < 0 {
+        return Err(nix::errno::Errno::last().into());
+    }
+    names.truncate(usize::try_from(size)?);
+    Ok(names
+        .split(|byte| *byte == 0)
+        .filter(|name| !name.is_empty())
+        .filter_map(|name| CString::new(name).ok())
+        .collect())
+}
+
+/// Copies all extended attributes (including ACLs and SELinux labels) from one opened file to another.
+/// Attributes, which cannot be set on the destination (e.g. missing file system support), get skipped.
+fn copy_xattrs(src_fd: RawFd, dst_fd: RawFd, path: &Path) -> anyhow::Result<()> {
+    for name in list_xattrs(src_fd)? {
+        let size = unsafe { nix::libc::fgetxattr(src_fd, name.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            return Err(nix::errno::Errno::last())
+                .with_context(|| format!("Unable to get xattr {name:?} of {path:?}"));
+        }
+        let mut value = vec![0u8; usize::try_from(size)?];
+        let size = unsafe {
+            nix::libc::fgetxattr(
+                src_fd,
+                name.as_ptr(),
+                value.as_mut_ptr() as *mut c_void,
+                value.len(),
+            )
+        };
+        if size < 0 {
+            return Err(nix::errno::Errno::last())
+                .with_context(|| format!("Unable to get xattr {name:?} of {path:?}"));
+        }
+        value.truncate(usize::try_from(size)?);
+
+        let result = unsafe {
+            nix::libc::fsetxattr(
+                dst_fd,
+                name.as_ptr(),
+                value.as_ptr() as *const c_void,
+                value.len(),
+                0,
+            )
+        };
+        if result < 0 {
+            log::warn!(
+                "Unable to set xattr {name:?} on {path:?}: {:?}",
+                nix::errno::Errno::last()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Removes an existing entry (except directories), so it can be replaced by a copy
+fn remove_existing_entry(dirfd: RawFd, name: &CStr, path: &Path) -> anyhow::Result<()> {
+    match nix::unistd::unlinkat(Some(dirfd), name, UnlinkatFlags::NoRemoveDir) {
+        Ok(()) | Err(nix::errno::Errno::ENOENT) => Ok(()),
+        Err(err) => Err(err).with_context(|| format!("Unable to replace {path:?}")),
+    }
+}
+
+/// Owner and options applied to all copied files
+struct CopyTarget {
+    uid: nix::unistd::Uid,
+    gid: nix::unistd::Gid,
+    copy_xattrs: bool,
+}
+
+/// Copies the contents of a directory into an existing directory and sets the given owner on all copied files.
+/// Symlinks are copied as symlinks (and never followed), modes and FIFOs are preserved, device nodes and sockets get skipped.
+/// Existing files get overwritten, so multiple directories can be layered on top of each other.
+/// If `copy_xattrs` is set, extended attributes (including ACLs and SELinux labels) are copied as well.
+pub fn copy_dir_contents(
+    src: &Path,
+    dst: &Path,
+    uid: nix::unistd::Uid,
+    gid: nix::unistd::Gid,
+    copy_xattrs: bool,
+) -> anyhow::Result<()> {
+    // the source directory itself is configured by the admin, so it might be a symlink
+    let mut src_dir = Dir::open(
+        src,
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )
+    .with_context(|| format!("Unable to open directory {src:?}"))?;
+    let dst_dir = Dir::open(dst, dir_open_flags(), Mode::empty())
+        .with_context(|| format!("Unable to open directory {dst:?}"))?;
+
+    let target = CopyTarget {
+        uid,
+        gid,
+        copy_xattrs,
+    };
+    copy_dir_entries(&mut src_dir, src, &dst_dir, dst, &target, 0)
+}
+
+fn copy_dir_entries(
+    src_dir: &mut Dir,
+    src_path: &Path,
+    dst_dir: &Dir,
+    dst_path: &Path,
+    target: &CopyTarget,
+    depth: usize,
+) -> anyhow::Result<()> {
+    if depth > MAX_DEPTH {
+        bail!("Directory {src_path:?} is nested too deeply");
+    }
+
+    let mut names = Vec::new();
+    for entry in src_dir.iter() {
+        let entry = entry.with_context(|| format!("Unable to read directory {src_path:?}"))?;
+        let name = entry.file_name().to_bytes();
+        if name != b"." && name != b".." {
+            names.push(CString::from(entry.file_name()));
+        }
+    }
+
+    let src_fd = src_dir.as_raw_fd();
+    let dst_fd = dst_dir.as_raw_fd();
+    for name in names {
+        let name = name.as_c_str();
+        let src_entry_path = src_path.join(OsStr::from_bytes(name.to_bytes()));
+        let dst_entry_path = dst_path.join(OsStr::from_bytes(name.to_bytes()));
+        let stat = nix::sys::stat::fstatat(Some(src_fd), name, AtFlags::AT_SYMLINK_NOFOLLOW)
+            .with_context(|| format!("Unable to stat {src_entry_path:?}"))?;
+        // guest users must not get setuid/setgid files (or directories) handed out by the skeleton
+        let mode =
+            Mode::from_bits_truncate(stat.st_mode & 0o7777) & !(Mode::S_ISUID | Mode::S_ISGID);
+
+        match SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT {
+            SFlag::S_IFDIR => {
+                let mut src_child =
+                    Dir::openat(Some(src_fd), name, dir_open_flags(), Mode::empty())
+                        .with_context(|| format!("Unable to open directory {src_entry_path:?}"))?;
+                match nix::sys::stat::mkdirat(Some(dst_fd), name, Mode::S_IRWXU) {
+                    Ok(()) | Err(nix::errno::Errno::EEXIST) => {}
+                    Err(err) => {
+                        return Err(err).with_context(|| {
+                            format!("Unable to create directory {dst_entry_path:?}")
+                        })
+                    }
+                }
+                // fails if an existing entry is not a directory (or a symlink to one)
+                let dst_child = Dir::openat(Some(dst_fd), name, dir_open_flags(), Mode::empty())
+                    .with_context(|| format!("Unable to open directory {dst_entry_path:?}"))?;
+                nix::unistd::fchown(dst_child.as_raw_fd(), Some(target.uid), Some(target.gid))
+                    .with_context(|| format!("Unable to chown {dst_entry_path:?}"))?;
+                if target.copy_xattrs {
+                    copy_xattrs(
+                        src_child.as_raw_fd(),
+                        dst_child.as_raw_fd(),
+                        &dst_entry_path,
+                    )?;
+                }
+                copy_dir_entries(
+                    &mut src_child,
+                    &src_entry_path,
+                    &dst_child,
+                    &dst_entry_path,
+                    target,
+                    depth + 1,
+                )?;
+                // set the mode last, so read-only directories can be filled
+                nix::sys::stat::fchmod(dst_child.as_raw_fd(), mode)
+                    .with_context(|| format!("Unable to set permissions of {dst_entry_path:?}"))?;
+            }
+            SFlag::S_IFREG => {
+                let src_file = nix::fcntl::openat(
+                    Some(src_fd),
+                    name,
+                    OFlag::O_RDONLY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC,
+                    Mode::empty(),
+                )
+                .with_context(|| format!("Unable to open {src_entry_path:?}"))?;
+                let mut src_file = unsafe { std::fs::File::from_raw_fd(src_file) };
+
+                remove_existing_entry(dst_fd, name, &dst_entry_path)?;
+                let dst_file = nix::fcntl::openat(
+                    Some(dst_fd),
+                    name,
+                    OFlag::O_WRONLY
+                        | OFlag::O_CREAT
+                        | OFlag::O_EXCL
+                        | OFlag::O_NOFOLLOW
+                        | OFlag::O_CLOEXEC,
+                    Mode::S_IRUSR | Mode::S_IWUSR,
+                )
+                .with_context(|| format!("Unable to create {dst_entry_path:?}"))?;
+                let mut dst_file = unsafe { std::fs::File::from_raw_fd(dst_file) };
+
+                std::io::copy(&mut src_file, &mut dst_file).with_context(|| {
+                    format!("Unable to copy {src_entry_path:?} to {dst_entry_path:?}")
+                })?;
+                nix::unistd::fchown(dst_file.as_raw_fd(), Some(target.uid), Some(target.gid))
+                    .with_context(|| format!("Unable to chown {dst_entry_path:?}"))?;
+                if target.copy_xattrs {
+                    copy_xattrs(src_file.as_raw_fd(), dst_file.as_raw_fd(), &dst_entry_path)?;
+                }
+                // set the mode after changing the owner, as chown would reset it otherwise
+                nix::sys::stat::fchmod(dst_file.as_raw_fd(), mode)
+                    .with_context(|| format!("Unable to set permissions of {dst_entry_path:?}"))?;
+            }
+            SFlag::S_IFLNK => {
+                let link_target = nix::fcntl::readlinkat(Some(src_fd), name)
+                    .with_context(|| format!("Unable to read symlink {src_entry_path:?}"))?;
+                remove_existing_entry(dst_fd, name, &dst_entry_path)?;
+                nix::unistd::symlinkat(link_target.as_os_str(), Some(dst_fd), name)
+                    .with_context(|| format!("Unable to create symlink {dst_entry_path:?}"))?;
+                nix::unistd::fchownat(
+                    Some(dst_fd),
+                    name,
+                    Some(target.uid),
+                    Some(target.gid),
+                    AtFlags::AT_SYMLINK_NOFOLLOW,
+                )
+                .with_context(|| format!("Unable to chown {dst_entry_path:?}"))?;
+            }
+            SFlag::S_IFIFO => {
+                remove_existing_entry(dst_fd, name, &dst_entry_path)?;
+                nix::unistd::mkfifoat(Some(dst_fd), name, Mode::S_IRUSR | Mode::S_IWUSR)
+                    .with_context(|| format!("Unable to create FIFO {dst_entry_path:?}"))?;
+                nix::unistd::fchownat(
+                    Some(dst_fd),
+                    name,
+                    Some(target.uid),
+                    Some(target.gid),
+                    AtFlags::AT_SYMLINK_NOFOLLOW,
+                )
+                .with_context(|| format!("Unable to chown {dst_entry_path:?}"))?;
+                nix::sys::stat::fchmodat(
+                    Some(dst_fd),
+                    name,
+                    mode,
+                    // Linux does not support changing the mode without following symlinks, but we just created the FIFO
+                    nix::sys::stat::FchmodatFlags::FollowSymlink,
+                )
+                .with_context(|| format!("Unable to set permissions of {dst_entry_path:?}"))?;
+            }
+            _ => log::warn!("{src_entry_path:?} is a device node or socket, skipping it!"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an empty directory for a single test
+    fn test_dir(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "guest-users-secure-fs-{}-{name}",
+            std::process::id()
+        ));
+        if path.exists() {
+            std::fs::remove_dir_all(&path).unwrap();
+        }
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn removes_nested_tree() {
+        let base = test_dir("nested");
+        let home = base.join("home");
+        std::fs::create_dir_all(home.join("a/b/c")).unwrap();
+        std::fs::write(home.join("file"), "content").unwrap();
+        std::fs::write(home.join("a/b/c/file"), "content").unwrap();
+        nix::unistd::mkfifo(&home.join("a/fifo"), Mode::S_IRWXU).unwrap();
+        std::os::unix::fs::symlink("/nonexistent", home.join("a/dangling")).unwrap();
+
+        assert!(remove_dir_all(&home).unwrap().is_empty());
+
+        assert!(!home.exists());
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn does_not_follow_symlinks() {
+        let base = test_dir("symlinks");
+        let home = base.join("home");
+        let outside = base.join("outside");
+        std::fs::create_dir_all(&home).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("precious"), "content").unwrap();
+        std::os::unix::fs::symlink(&outside, home.join("dir_link")).unwrap();
+        std::os::unix::fs::symlink(outside.join("precious"), home.join("file_link")).unwrap();
+
+        assert!(remove_dir_all(&home).unwrap().is_empty());
+
+        assert!(!home.exists());
+        assert_eq!(
+            std::fs::read_to_string(outside.join("precious")).unwrap(),
+            "content"
+        );
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn refuses_symlinked_directory() {
+        let base = test_dir("symlinked-root");
+        let target = base.join("target");
+        std::fs::create_dir_all(&target).unwrap();
+        std::fs::write(target.join("precious"), "content").unwrap();
+        std::os::unix::fs::symlink(&target, base.join("home")).unwrap();
+
+        assert!(remove_dir_all(&base.join("home")).is_err());
+
+        assert!(target.join("precious").exists());
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn skips_mount_points() {
+        let base = test_dir("mount-points");
+        let home = base.join("home");
+        std::fs::create_dir_all(home.join("mnt")).unwrap();
+        std::fs::create_dir_all(home.join("other")).unwrap();
+        std::fs::write(home.join("mnt/precious"), "content").unwrap();
+        std::fs::write(home.join("other/file"), "content").unwrap();
+
+        let skipped = remove_dir_all_except(&home, &[home.join("mnt")]).unwrap();
+
+        assert_eq!(skipped, vec![home.join("mnt")]);
+        assert!(home.join("mnt/precious").exists());
+        assert!(!home.join("other").exists());
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn keeps_parents_of_skipped_mount_points() {
+        let base = test_dir("nested-mount-points");
+        let home = base.join("home");
+        std::fs::create_dir_all(home.join("a/fuse")).unwrap();
+        std::fs::create_dir_all(home.join("b")).unwrap();
+        std::fs::write(home.join("a/file"), "content").unwrap();
+        std::fs::write(home.join("b/file"), "content").unwrap();
+
+        let skipped = remove_dir_all_except(&home, &[home.join("a/fuse")]).unwrap();
+
+        // the caller gets the skipped mount point reported, everything else is gone
+        assert_eq!(skipped, vec![home.join("a/fuse")]);
+        assert!(home.join("a/fuse").is_dir());
+        assert!(!home.join("a/file").exists());
+        assert!(!home.join("b").exists());
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn removes_deeply_nested_tree() {
+        let base = test_dir("deeply-nested");
+        let home = base.join("home");
+        let mut deepest = home.clone();
+        for _ in 0..(MAX_DEPTH * 2) {
+            deepest.push("d");
+        }
+        std::fs::create_dir_all(&deepest).unwrap();
+        std::fs::write(deepest.join("file"), "content").unwrap();
+
+        assert!(remove_dir_all(&home).unwrap().is_empty());
+
+        assert!(!home.exists());
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn parses_mountinfo() {
+        let mountinfo = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+36 22 0:32 / /home/guest-users/guest-31001 rw,nosuid,nodev - tmpfs tmpfs rw,size=1048576k
+37 36 8:1 /srv /home/guest-users/guest-31001/my\\040files rw - ext4 /dev/sda1 rw
+";
+        assert_eq!(
+            parse_mountinfo(mountinfo),
+            vec![
+                PathBuf::from("/"),
+                PathBuf::from("/home/guest-users/guest-31001"),
+                PathBuf::from("/home/guest-users/guest-31001/my files"),
+            ]
+        );
+    }
+
+    /// Owner of the files created by the tests, as tests are not necessarily running as root
+    fn current_owner() -> (nix::unistd::Uid, nix::unistd::Gid) {
+        (nix::unistd::getuid(), nix::unistd::getgid())
+    }
+
+    #[test]
+    fn copies_tree_preserving_types_and_modes() {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+        let base = test_dir("copy");
+        let skel = base.join("skel");
+        let home = base.join("home");
+        std::fs::create_dir_all(skel.join("dir/nested")).unwrap();
+        std::fs::create_dir_all(&home).unwrap();
+        std::fs::write(skel.join("dir/file"), "content").unwrap();
+        std::fs::set_permissions(skel.join("dir/file"), PermissionsExt::from_mode(0o640)).unwrap();
+        std::fs::write(skel.join("script"), "#!/bin/sh").unwrap();
+        std::fs::set_permissions(skel.join("script"), PermissionsExt::from_mode(0o755)).unwrap();
+        std::fs::set_permissions(skel.join("dir"), PermissionsExt::from_mode(0o750)).unwrap();
+        std::os::unix::fs::symlink("dir/file", skel.join("relative_link")).unwrap();
+        std::os::unix::fs::symlink("/nonexistent", skel.join("dangling_link")).unwrap();
+        nix::unistd::mkfifo(&skel.join("fifo"), Mode::from_bits_truncate(0o600)).unwrap();
+
+        let (uid, gid) = current_owner();
+        copy_dir_contents(&skel, &home, uid, gid, false).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(home.join("dir/file")).unwrap(),
+            "content"
+        );
+        let mode = |path: &str| {
+            std::fs::symlink_metadata(home.join(path))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o7777
+        };
+        assert_eq!(mode("dir/file"), 0o640);
+        assert_eq!(mode("script"), 0o755);
+        assert_eq!(mode("dir"), 0o750);
+        assert_eq!(mode("fifo"), 0o600);
+        assert!(home.join("dir/nested").is_dir());
+        assert_eq!(
+            std::fs::read_link(home.join("relative_link")).unwrap(),
+            PathBuf::from("dir/file")
+        );
+        assert_eq!(
+            std::fs::read_link(home.join("dangling_link")).unwrap(),
+            PathBuf::from("/nonexistent")
+        );
+        assert!(std::fs::symlink_metadata(home.join("fifo"))
+            .unwrap()
+            .file_type()
+            .is_fifo());
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn drops_setuid_and_setgid_bits_when_copying() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let base = test_dir("copy-setuid");
+        let skel = base.join("skel");
+        let home = base.join("home");
+        std::fs::create_dir_all(skel.join("dir")).unwrap();
+        std::fs::create_dir_all(&home).unwrap();
+        std::fs::write(skel.join("setuid"), "#!/bin/sh").unwrap();
+        std::fs::set_permissions(skel.join("setuid"), PermissionsExt::from_mode(0o6755)).unwrap();
+        std::fs::set_permissions(skel.join("dir"), PermissionsExt::from_mode(0o2775)).unwrap();
+
+        let (uid, gid) = current_owner();
+        copy_dir_contents(&skel, &home, uid, gid, false).unwrap();
+
+        let mode = |path: &str| {
+            std::fs::symlink_metadata(home.join(path))
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o7777
+        };
+        assert_eq!(mode("setuid"), 0o755);
+        assert_eq!(mode("dir"), 0o775);
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn does_not_follow_symlinks_when_copying() {
+        let base = test_dir("copy-symlinks");
+        let skel = base.join("skel");
+        let home = base.join("home");
+        let outside = base.join("outside");
+        std::fs::create_dir_all(&skel).unwrap();
+        std::fs::create_dir_all(&home).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(outside.join("secret"), "secret").unwrap();
+        std::os::unix::fs::symlink(&outside, skel.join("dir_link")).unwrap();
+
+        let (uid, gid) = current_owner();
+        copy_dir_contents(&skel, &home, uid, gid, false).unwrap();
+
+        assert!(std::fs::symlink_metadata(home.join("dir_link"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(
+            std::fs::read_dir(&home).unwrap().count(),
+            1,
+            "only the symlink itself should have been copied"
+        );
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn overwrites_existing_files() {
+        let base = test_dir("copy-overwrite");
+        let skel = base.join("skel");
+        let home = base.join("home");
+        std::fs::create_dir_all(skel.join("dir")).unwrap();
+        std::fs::create_dir_all(home.join("dir")).unwrap();
+        std::fs::write(skel.join("dir/file"), "new").unwrap();
+        std::fs::write(skel.join("link"), "file replacing a symlink").unwrap();
+        std::fs::write(home.join("dir/file"), "old").unwrap();
+        std::fs::write(home.join("dir/other"), "untouched").unwrap();
+        std::os::unix::fs::symlink("/nonexistent", home.join("link")).unwrap();
+
+        let (uid, gid) = current_owner();
+        copy_dir_contents(&skel, &home, uid, gid, false).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(home.join("dir/file")).unwrap(),
+            "new"
+        );
+        assert_eq!(
+            std::fs::read_to_string(home.join("dir/other")).unwrap(),
+            "untouched"
+        );
+        assert_eq!(
+            std::fs::read_to_string(home.join("link")).unwrap(),
+            "file replacing a symlink"
+        );
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn copies_xattrs_if_requested() {
+        let base = test_dir("copy-xattrs");
+        let skel = base.join("skel");
+        let home = base.join("home");
+        let home_without_xattrs = base.join("home-without-xattrs");
+        std::fs::create_dir_all(&skel).unwrap();
+        std::fs::create_dir_all(&home).unwrap();
+        std::fs::create_dir_all(&home_without_xattrs).unwrap();
+        std::fs::write(skel.join("file"), "content").unwrap();
+
+        let file = std::fs::File::open(skel.join("file")).unwrap();
+        let name = CString::new("user.guest_users_test").unwrap();
+        let value = b"value";
+        let result = unsafe {
+            nix::libc::fsetxattr(
+                file.as_raw_fd(),
+                name.as_ptr(),
+                value.as_ptr() as *const c_void,
+                value.len(),
+                0,
+            )
+        };
+        if result < 0 {
+            // the file system of the temp dir does not support user xattrs
+            std::fs::remove_dir_all(&base).unwrap();
+            return;
+        }
+
+        let (uid, gid) = current_owner();
+        copy_dir_contents(&skel, &home, uid, gid, true).unwrap();
+        copy_dir_contents(&skel, &home_without_xattrs, uid, gid, false).unwrap();
+
+        let copied = std::fs::File::open(home.join("file")).unwrap();
+        assert_eq!(list_xattrs(copied.as_raw_fd()).unwrap(), vec![name]);
+        let not_copied = std::fs::File::open(home_without_xattrs.join("file")).unwrap();
+        assert!(list_xattrs(not_copied.as_raw_fd()).unwrap().is_empty());
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+}
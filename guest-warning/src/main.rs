@@ -4,6 +4,9 @@
 
 use clap::Parser;
 
+/// Minutes before the end of a time-limited guest session at which the guest user gets warned
+const SESSION_WARNING_MINUTES: [i64; 3] = [10, 5, 1];
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
@@ -29,36 +32,80 @@ trait Notifications {
     ) -> zbus::Result<u32>;
 }
 
-async fn notify_if_guest_user() -> anyhow::Result<()> {
-    let global_settings = guest_users_lib::helper::get_config()?;
-
-    let cur_user_id = nix::unistd::Uid::current();
-    // check whether this user id belongs to a guest user
-    let mut db = guest_users_lib::db::DB::new(&global_settings)?;
-    if db.find_user_by_id(cur_user_id.as_raw())?.is_none() {
-        log::debug!("User does not seem to be a guest user (not found in guest users DB)");
-        return Ok(());
-    }
-
-    log::trace!("Setting up zbus connection...");
-    let connection = zbus::Connection::session().await?;
-    log::trace!("Setting up zbus NotificationsProxy...");
-    let proxy = NotificationsProxy::new(&connection).await?;
-
+async fn send_notification(
+    proxy: &NotificationsProxy<'_>,
+    global_settings: &guest_users_lib::helper::Config,
+    summary: &str,
+    body: &str,
+) -> anyhow::Result<()> {
     log::trace!("Sending notification...");
     let reply = proxy
         .notify(
             &global_settings.guest_user_warning_app_name,
             0,
             "warning",
-            &global_settings.guest_user_warning_title,
-            &global_settings.guest_user_warning_body,
+            summary,
+            body,
             &[],
             std::collections::HashMap::from([("urgency", &zbus::zvariant::Value::I16(2))]),
             0,
         )
         .await?;
     log::debug!("Got notification ID={reply}");
+    Ok(())
+}
+
+async fn notify_if_guest_user() -> anyhow::Result<()> {
+    let global_settings = guest_users_lib::helper::get_config()?;
+
+    let cur_user_id = nix::unistd::Uid::current();
+    // check whether this user id belongs to a guest user
+    let mut db = guest_users_lib::db::DB::new(&global_settings)?;
+    let user = match db.find_user_by_id(cur_user_id.as_raw())? {
+        Some(user) => user,
+        None => {
+            log::debug!("User does not seem to be a guest user (not found in guest users DB)");
+            return Ok(());
+        }
+    };
+
+    log::trace!("Setting up zbus connection...");
+    let connection = zbus::Connection::session().await?;
+    log::trace!("Setting up zbus NotificationsProxy...");
+    let proxy = NotificationsProxy::new(&connection).await?;
+
+    send_notification(
+        &proxy,
+        &global_settings,
+        &global_settings.guest_user_warning_title,
+        &global_settings.guest_user_warning_body,
+    )
+    .await?;
+
+    // count down to the end of time-limited sessions
+    if let Some(deadline) = guest_users_lib::helper::session_deadline(&global_settings, &user) {
+        for minutes in SESSION_WARNING_MINUTES {
+            let warning_time = deadline - chrono::Duration::minutes(minutes);
+            let time_until_warning = warning_time - chrono::Utc::now().naive_utc();
+            // skip warnings, which are already due (e.g. on re-login)
+            let time_until_warning = match time_until_warning.to_std() {
+                Ok(time_until_warning) => time_until_warning,
+                Err(_) => continue,
+            };
+
+            log::debug!("Waiting {time_until_warning:?} for next session end warning");
+            tokio::time::sleep(time_until_warning).await;
+            send_notification(
+                &proxy,
+                &global_settings,
+                &global_settings.guest_session_warning_title,
+                &global_settings
+                    .guest_session_warning_body
+                    .replace("{minutes}", &minutes.to_string()),
+            )
+            .await?;
+        }
+    }
 
     Ok(())
 }
@@ -66,6 +113,7 @@ async fn notify_if_guest_user() -> anyhow::Result<()> {
 fn main() -> anyhow::Result<()> {
     tokio::runtime::Builder::new_current_thread()
         .enable_io()
+        .enable_time()
         .build()
         .unwrap()
         .block_on(main_async())
@@ -56,3 +56,33 @@ pub fn get_entry_by_name(name: &str) -> Result<Response<Group>, Error> {
 
     Ok(Response::NotFound)
 }
+
+/// Returns all groups a user is a member of apart from its primary group.
+/// This backs initgroups/getgrouplist, which only make use of the group IDs of the returned groups.
+pub fn get_entries_by_user(name: &str) -> Result<Response<Vec<Group>>, Error> {
+    let global_settings = guest_users_lib::helper::get_config()?;
+    let mut db = guest_users_lib::db::DB::new(&global_settings)?;
+
+    let user = match db.find_user_by_name(name)? {
+        Some(user) => user,
+        None => return Ok(Response::NotFound),
+    };
+
+    let mut groups = Vec::new();
+    for membership in db.find_groups_for_user(&user)? {
+        // Supplementary groups are usually system groups, which are not known to our database.
+        // We do not look them up via NSS again, as this would end up in recursive NSS calls.
+        let group_name = db
+            .find_group_by_id(membership.group_id as u32)?
+            .map(|group| group.group_name)
+            .unwrap_or_default();
+        groups.push(Group {
+            name: group_name,
+            passwd: "x".to_string(), // disable password for group
+            gid: membership.group_id as u32,
+            members: vec![user.user_name.to_string()],
+        });
+    }
+
+    Ok(Response::Success(groups))
+}
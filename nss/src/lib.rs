@@ -1,6 +1,7 @@
 extern crate libc;
 
 use libnss::group::{Group, GroupHooks};
+use libnss::initgroups::InitgroupsHooks;
 use libnss::interop::Response;
 use libnss::passwd::{Passwd, PasswdHooks};
 use libnss::shadow::{Shadow, ShadowHooks};
@@ -134,3 +135,23 @@ impl GroupHooks for GuestUserGroups {
         }
     }
 }
+
+struct GuestUserInitgroups;
+libnss::libnss_initgroups_hooks!(guest_users, GuestUserInitgroups);
+
+impl InitgroupsHooks for GuestUserInitgroups {
+    fn get_entries_by_user(user: String) -> Response<Vec<Group>> {
+        guest_users_lib::helper::init_logger();
+        log::trace!("get_entries_by_user (initgroups)");
+        match group::get_entries_by_user(&user) {
+            Ok(result) => {
+                log::trace!("get_entries_by_user (initgroups): ok");
+                result
+            }
+            Err(err) => {
+                log::warn!("Could not get groups by user name (initgroups): {err:?}");
+                Response::Unavail
+            }
+        }
+    }
+}
@@ -10,11 +10,7 @@ fn db_to_passwd(
 ) -> Result<Passwd, Error> {
     let gecos = gecos::Gecos {
         full_name: Some(
-            format!(
-                "{} ({})",
-                global_settings.guest_username_human_readable_prefix, user.id
-            )
-            .try_into()?,
+            guest_users_lib::helper::human_readable_user_name(global_settings, user).try_into()?,
         ),
         room: None,
         work_phone: None,
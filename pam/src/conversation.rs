@@ -0,0 +1,104 @@
+//! Minimal bindings to the PAM conversation function (see pam_conv(3)), as the pam crate does not expose them to modules
+
+use std::{
+    ffi::{c_void, CStr, CString},
+    os::raw::{c_char, c_int},
+};
+
+use anyhow::{anyhow, bail, Context, Error};
+use pam::{PamHandle, PamItemType};
+
+const PAM_SUCCESS: c_int = 0;
+
+/// Style of a message sent to the application (values of Linux-PAM's `_pam_types.h`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageStyle {
+    /// Ask for input without echoing it (e.g. passwords)
+    PromptEchoOff = 1,
+    /// Show an error message
+    ErrorMsg = 3,
+    /// Show an informational message
+    TextInfo = 4,
+}
+
+#[repr(C)]
+struct PamMessage {
+    msg_style: c_int,
+    msg: *const c_char,
+}
+
+#[repr(C)]
+struct PamResponse {
+    resp: *mut c_char,
+    resp_retcode: c_int,
+}
+
+#[repr(C)]
+struct PamConv {
+    conv: Option<
+        extern "C" fn(
+            num_msg: c_int,
+            msg: *mut *const PamMessage,
+            resp: *mut *mut PamResponse,
+            appdata_ptr: *mut c_void,
+        ) -> c_int,
+    >,
+    appdata_ptr: *mut c_void,
+}
+
+/// Sends a single message to the application and returns its response (if any)
+pub fn converse(
+    handle: &PamHandle,
+    style: MessageStyle,
+    message: &str,
+) -> Result<Option<String>, Error> {
+    let conv = pam::get_item(handle, PamItemType::Conv)? as *const PamConv;
+    if conv.is_null() {
+        bail!("No PAM conversation set on PAM handle");
+    }
+    let conv = unsafe { &*conv };
+    let conv_function = conv
+        .conv
+        .context("No PAM conversation function set on PAM handle")?;
+
+    let message = CString::new(message)?;
+    let pam_message = PamMessage {
+        msg_style: style as c_int,
+        msg: message.as_ptr(),
+    };
+    let mut pam_message_ptr: *const PamMessage = &pam_message;
+    let mut pam_response_ptr: *mut PamResponse = std::ptr::null_mut();
+
+    let result = conv_function(
+        1,
+        &mut pam_message_ptr,
+        &mut pam_response_ptr,
+        conv.appdata_ptr,
+    );
+    if result != PAM_SUCCESS {
+        return Err(anyhow!("PAM conversation failed with code {result}"));
+    }
+    if pam_response_ptr.is_null() {
+        return Ok(None);
+    }
+
+    // the response got allocated by the application, so we are responsible for freeing it
+    let response = unsafe {
+        let response_text = (*pam_response_ptr).resp;
+        let response = if response_text.is_null() {
+            None
+        } else {
+            let response = CStr::from_ptr(response_text).to_str().map(String::from);
+            // wipe the response, as it might contain secrets like PINs
+            std::ptr::write_bytes(response_text, 0, nix::libc::strlen(response_text));
+            nix::libc::free(response_text as *mut c_void);
+            Some(response)
+        };
+        nix::libc::free(pam_response_ptr as *mut c_void);
+        response
+    };
+
+    response
+        .transpose()
+        .context("Invalid PAM conversation response (no UTF-8)")
+}
@@ -1,13 +1,17 @@
 use std::{
     convert::TryFrom,
     ffi::{c_void, CStr, CString},
-    os::raw::c_char,
+    os::raw::{c_char, c_int},
 };
 
 use anyhow::{Context, Error};
+use guest_users_lib::daemon_socket;
+use guest_users_lib::helper::UnlockPinMode;
 use nix::unistd::Uid;
 use pam::{PamHandle, PamItemType, PamReturnCode};
 
+use crate::conversation::{converse, MessageStyle};
+
 pub fn account_management(
     handle: &PamHandle,
     _args: Vec<&std::ffi::CStr>,
@@ -26,13 +30,128 @@ pub fn account_management(
     Ok(PamReturnCode::Ignore)
 }
 
-fn get_user_from_handle(handle: &mut PamHandle) -> Result<String, Error> {
-    Ok(
-        unsafe { CStr::from_ptr(pam::get_item(handle, PamItemType::User)? as *mut c_char) }
+/// PAM environment variable the ID of the recorded session is stored in between opening and closing a session
+const SESSION_ID_ENV: &str = "GUEST_USERS_SESSION_ID";
+
+extern "C" {
+    fn pam_getenv(pamh: *const c_void, name: *const c_char) -> *const c_char;
+    fn pam_putenv(pamh: *mut c_void, name_value: *const c_char) -> c_int;
+}
+
+/// Returns a variable of the PAM environment
+fn get_env(handle: &PamHandle, name: &str) -> Result<Option<String>, Error> {
+    let name = CString::new(name)?;
+    let value = unsafe { pam_getenv(handle as *const PamHandle as *const c_void, name.as_ptr()) };
+    if value.is_null() {
+        return Ok(None);
+    }
+    Ok(Some(
+        unsafe { CStr::from_ptr(value) }
+            .to_str()
+            .context("Invalid return for PAM getenv (no UTF-8)")?
+            .to_string(),
+    ))
+}
+
+/// Sets a variable of the PAM environment.
+/// In contrast to `pam::putenv`, this works with the shared PAM handle passed to the session hooks.
+fn put_env(handle: &PamHandle, name: &str, value: &str) -> Result<(), Error> {
+    let name_value = CString::new(format!("{name}={value}"))?;
+    let result = unsafe {
+        pam_putenv(
+            handle as *const PamHandle as *mut c_void,
+            name_value.as_ptr(),
+        )
+    };
+    if result != 0 {
+        anyhow::bail!("PAM putenv failed with code {result}");
+    }
+    Ok(())
+}
+
+/// Returns a string item (like the user or tty) of the PAM handle
+fn get_string_item(handle: &PamHandle, item_type: PamItemType) -> Result<Option<String>, Error> {
+    let item = pam::get_item(handle, item_type)? as *const c_char;
+    if item.is_null() {
+        return Ok(None);
+    }
+    Ok(Some(
+        unsafe { CStr::from_ptr(item) }
             .to_str()
             .context("Invalid return for PAM get_item (no UTF-8)")?
             .to_string(),
-    )
+    ))
+}
+
+fn get_user_from_handle(handle: &mut PamHandle) -> Result<String, Error> {
+    get_string_item(handle, PamItemType::User)?.context("No user set on PAM handle")
+}
+
+/// Determines the logind seat a login request is coming from.
+/// Returns `None` for requests not being bound to a seat, like remote logins.
+fn get_request_seat(handle: &PamHandle) -> Result<Option<String>, Error> {
+    // remote logins are never bound to a seat, even if they claim one via the PAM environment
+    if let Some(rhost) = get_string_item(handle, PamItemType::RHost)? {
+        if !rhost.is_empty() {
+            return Ok(None);
+        }
+    }
+
+    // display managers like gdm or lightdm announce the seat via the PAM environment
+    if let Some(seat) = get_env(handle, "XDG_SEAT")? {
+        if !seat.is_empty() {
+            return Ok(Some(seat));
+        }
+    }
+
+    // virtual terminals always belong to the first seat
+    if let Some(tty) = get_string_item(handle, PamItemType::TTY)? {
+        if let Some(vt_number) = tty.trim_start_matches("/dev/").strip_prefix("tty") {
+            if !vt_number.is_empty() && vt_number.chars().all(|c| c.is_ascii_digit()) {
+                return Ok(Some("seat0".to_string()));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Determines the unlock PIN of a new guest user according to the configured mode.
+/// Returns `Err` if the guest user failed to choose a valid PIN.
+fn get_new_unlock_pin(
+    handle: &PamHandle,
+    global_settings: &guest_users_lib::helper::Config,
+) -> Result<Option<String>, Error> {
+    let pin_length = global_settings.guest_unlock_pin_length;
+    match global_settings.guest_unlock_pin {
+        UnlockPinMode::Off => Ok(None),
+        UnlockPinMode::Generate => Ok(Some(guest_users_lib::helper::generate_unlock_pin(
+            pin_length,
+        ))),
+        UnlockPinMode::Choose => {
+            let pin = converse(
+                handle,
+                MessageStyle::PromptEchoOff,
+                &format!("Choose a PIN (at least {pin_length} digits) to unlock this guest session later:"),
+            )?
+            .unwrap_or_default();
+            if i64::try_from(pin.len())? < pin_length || !pin.chars().all(|c| c.is_ascii_digit()) {
+                converse(
+                    handle,
+                    MessageStyle::ErrorMsg,
+                    &format!("The PIN has to consist of at least {pin_length} digits."),
+                )?;
+                anyhow::bail!("Guest user chose an invalid unlock PIN");
+            }
+            let repeated_pin = converse(handle, MessageStyle::PromptEchoOff, "Repeat the PIN:")?
+                .unwrap_or_default();
+            if pin != repeated_pin {
+                converse(handle, MessageStyle::ErrorMsg, "The PINs do not match.")?;
+                anyhow::bail!("Guest user repeated the unlock PIN incorrectly");
+            }
+            Ok(Some(pin))
+        }
+    }
 }
 
 pub fn authenticate(
@@ -59,10 +178,32 @@ pub fn authenticate(
             return Ok(PamReturnCode::Auth_Err);
         }
 
+        // ask for the PIN prior to creating the user, so we do not end up with unused guest users
+        let unlock_pin = match get_new_unlock_pin(handle, &global_settings) {
+            Ok(unlock_pin) => unlock_pin,
+            Err(err) => {
+                log::info!("Unable to determine unlock PIN, preventing login: {err:?}");
+                return Ok(PamReturnCode::Auth_Err);
+            }
+        };
+
         pam::putenv(handle, "IS_GUEST_USER=true")?;
 
-        // create completely new user
-        let new_user = db.create_guest_user()?;
+        // create completely new user, the PIN gets stored along with it
+        let unlock_pin_hash = match &unlock_pin {
+            Some(unlock_pin) => Some(guest_users_lib::helper::hash_unlock_pin(unlock_pin)?),
+            None => None,
+        };
+        let new_user = db.create_guest_user(get_request_seat(handle)?, unlock_pin_hash)?;
+        if let Some(unlock_pin) = unlock_pin {
+            if global_settings.guest_unlock_pin == UnlockPinMode::Generate {
+                converse(
+                    handle,
+                    MessageStyle::TextInfo,
+                    &format!("Your PIN to unlock this guest session is: {unlock_pin}"),
+                )?;
+            }
+        }
         let new_user_name = CString::new(new_user.user_name)?;
         pam::set_item(
             handle,
@@ -88,6 +229,12 @@ pub fn authenticate(
             return Ok(PamReturnCode::Auth_Err);
         }
 
+        // do not let guest users back in once their maximum session duration is exceeded
+        if guest_users_lib::helper::is_session_deadline_exceeded(&global_settings, &user) {
+            log::info!("User exceeded its maximum session duration, preventing login!");
+            return Ok(PamReturnCode::Auth_Err);
+        }
+
         // Check whether the login is coming from a root user to prevent other (non-elevated) users to log-in as guest users
         // E.g. only gdm and the user itself should be allowed to (re-)login as a guest user, but not other users
         if !Uid::current().is_root() && Uid::current().as_raw() != u32::try_from(user.id)? {
@@ -95,17 +242,135 @@ pub fn authenticate(
             return Ok(PamReturnCode::Auth_Err);
         }
 
+        // bind re-login to the seat the guest user got created on, so nobody on another seat or remote can take over the session
+        let seat_matches = if Uid::current().is_root() {
+            let request_seat = get_request_seat(handle)?;
+            request_seat.is_some() && request_seat == user.seat_id
+        } else {
+            // the guest user re-authenticates itself (e.g. screen locker), so the request is coming from within one of its sessions
+            std::env::var("XDG_SEAT")
+                .ok()
+                .filter(|seat| !seat.is_empty())
+                == user.seat_id
+        };
+        if !seat_matches {
+            log::warn!(
+                "Login request is not coming from seat {:?} the user got created on, preventing login!",
+                user.seat_id
+            );
+            return Ok(PamReturnCode::Auth_Err);
+        }
+
         // prevent logging in users without any running sessions (in order to prevent anyone to log in as a previous guest user if no reboot has happened)
-        if !guest_users_lib::helper::has_active_user_sessions(login_username)? {
+        if !guest_users_lib::helper::has_active_user_sessions(&global_settings, login_username)? {
             log::warn!("User has no associated sessions, preventing login!");
             return Ok(PamReturnCode::Auth_Err);
         } else {
-            log::debug!("User has at least one associated session, allowing login");
+            log::debug!("User has at least one associated session");
+        }
+
+        // guest users created while unlock PINs were disabled do not have any PIN, so they keep re-login without one
+        if let Some(unlock_pin_hash) = &user.unlock_pin_hash {
+            let pin = converse(handle, MessageStyle::PromptEchoOff, "PIN:")?.unwrap_or_default();
+            if !guest_users_lib::helper::verify_unlock_pin(&pin, unlock_pin_hash)? {
+                log::warn!("User entered a wrong unlock PIN, preventing login!");
+                return Ok(PamReturnCode::Auth_Err);
+            }
         }
 
+        log::debug!("Allowing login");
         Ok(PamReturnCode::Success)
     } else {
         log::debug!("Username {login_username} does not match.");
         Ok(PamReturnCode::Ignore)
     }
 }
+
+pub fn open_session(
+    handle: &PamHandle,
+    _args: Vec<&std::ffi::CStr>,
+    _flags: std::os::raw::c_uint,
+) -> Result<PamReturnCode, Error> {
+    let login_user = pam::get_user(handle, None)?;
+
+    let global_settings = guest_users_lib::helper::get_config()?;
+    let mut db = guest_users_lib::db::DB::new(&global_settings)?;
+
+    let user = match db.find_user_by_name(login_user)? {
+        Some(user) => user,
+        None => return Ok(PamReturnCode::Ignore),
+    };
+
+    let service = get_string_item(handle, PamItemType::Service)?;
+    let tty = get_string_item(handle, PamItemType::TTY)?;
+    let rhost = get_string_item(handle, PamItemType::RHost)?.filter(|rhost| !rhost.is_empty());
+    let seat_id = get_request_seat(handle)?;
+
+    // only root is able to write to the database, otherwise the cleanup daemon records the session
+    let session_id = if nix::unistd::geteuid().is_root() {
+        // set up before recording the session, so a failure does not leave an open session behind
+        if global_settings.guest_private_tmp
+            && crate::namespace::is_interactive_service(service.as_deref())
+        {
+            crate::namespace::setup_private_tmp(&global_settings)?;
+        }
+
+        db.start_session(&user, service, tty, rhost, seat_id)?.id
+    } else {
+        log::debug!(
+            "Not running as root, asking cleanup daemon to record session of user {login_user}"
+        );
+        match daemon_socket::send_request(&daemon_socket::Request::OpenSession {
+            user_name: login_user.to_string(),
+            service,
+            tty,
+            rhost,
+            seat_id,
+        })? {
+            daemon_socket::Response::SessionOpened { session_id } => session_id,
+            response => anyhow::bail!("Unexpected response of cleanup daemon {response:?}"),
+        }
+    };
+    log::info!("Opened session {session_id} of user {login_user}");
+    put_env(handle, SESSION_ID_ENV, &session_id.to_string())?;
+
+    Ok(PamReturnCode::Success)
+}
+
+pub fn close_session(
+    handle: &PamHandle,
+    _args: Vec<&std::ffi::CStr>,
+    _flags: std::os::raw::c_uint,
+) -> Result<PamReturnCode, Error> {
+    // sessions of other users did not get recorded
+    let session_id = match get_env(handle, SESSION_ID_ENV)? {
+        Some(session_id) => session_id
+            .parse::<i64>()
+            .with_context(|| format!("Invalid {SESSION_ID_ENV} '{session_id}'"))?,
+        None => return Ok(PamReturnCode::Ignore),
+    };
+
+    // record the end of the session right away, so the history stays correct even if the cleanup daemon is not running
+    if nix::unistd::geteuid().is_root() {
+        let global_settings = guest_users_lib::helper::get_config()?;
+        let mut db = guest_users_lib::db::DB::new(&global_settings)?;
+        match db.end_session(session_id)? {
+            Some(session) => log::info!(
+                "Closed session {} of user {}",
+                session.id,
+                session.user_name
+            ),
+            None => log::warn!("Session {session_id} is not open anymore"),
+        }
+    }
+
+    // the cleanup daemon cleans up the guest user once its last session ended, outside of the process (and mount namespace)
+    // of the caller. If the daemon is not reachable, its reconciler catches up once it runs again.
+    if let Err(err) =
+        daemon_socket::send_request(&daemon_socket::Request::CloseSession { session_id })
+    {
+        log::warn!("Unable to notify cleanup daemon about closed session {session_id}: {err:?}");
+    }
+
+    Ok(PamReturnCode::Success)
+}
@@ -1,6 +1,8 @@
 use pam::{export_pam_module, PamHandle, PamModule, PamReturnCode};
 
+mod conversation;
 mod handler;
+mod namespace;
 
 pub struct GuestUserPAMModule;
 impl PamModule for GuestUserPAMModule {
@@ -54,21 +56,43 @@ impl PamModule for GuestUserPAMModule {
     }
 
     fn close_session(
-        _handle: &PamHandle,
-        _args: Vec<&std::ffi::CStr>,
-        _flags: std::os::raw::c_uint,
+        handle: &PamHandle,
+        args: Vec<&std::ffi::CStr>,
+        flags: std::os::raw::c_uint,
     ) -> PamReturnCode {
+        guest_users_lib::helper::init_logger();
         log::trace!("Close session");
-        PamReturnCode::Ignore
+
+        match handler::close_session(handle, args, flags) {
+            Ok(result) => {
+                log::trace!("close_session: ok");
+                result
+            }
+            Err(err) => {
+                log::warn!("close_session failure: {err:?}");
+                PamReturnCode::Session_Err
+            }
+        }
     }
 
     fn open_session(
-        _handle: &PamHandle,
-        _args: Vec<&std::ffi::CStr>,
-        _flags: std::os::raw::c_uint,
+        handle: &PamHandle,
+        args: Vec<&std::ffi::CStr>,
+        flags: std::os::raw::c_uint,
     ) -> PamReturnCode {
+        guest_users_lib::helper::init_logger();
         log::trace!("Open session");
-        PamReturnCode::Ignore
+
+        match handler::open_session(handle, args, flags) {
+            Ok(result) => {
+                log::trace!("open_session: ok");
+                result
+            }
+            Err(err) => {
+                log::warn!("open_session failure: {err:?}");
+                PamReturnCode::Session_Err
+            }
+        }
     }
 
     fn set_credentials(
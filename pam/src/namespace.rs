@@ -0,0 +1,59 @@
+//! Per-session private temporary directories, similar to pam_namespace(8)
+
+use anyhow::{Context, Error};
+use guest_users_lib::helper::Config;
+use nix::{
+    mount::{mount, MsFlags},
+    sched::{unshare, CloneFlags},
+};
+
+/// PAM services of non-interactive sessions, which keep sharing the temporary directories with the host.
+/// Especially the systemd user manager must not be moved, as it outlives the session and is shared by all sessions
+/// of the user.
+const NON_INTERACTIVE_SERVICES: &[&str] = &["systemd-user", "cron", "atd"];
+
+/// Returns whether sessions opened by the given PAM service get private temporary directories
+pub fn is_interactive_service(service: Option<&str>) -> bool {
+    !service.is_some_and(|service| NON_INTERACTIVE_SERVICES.contains(&service))
+}
+
+/// Moves the calling process into a new mount namespace and mounts an empty tmpfs on all
+/// `guest_private_tmp_paths`. All processes of the session inherit the namespace, so the
+/// mounts (and their contents) vanish once the last process of the session exited.
+pub fn setup_private_tmp(settings: &Config) -> Result<(), Error> {
+    unshare(CloneFlags::CLONE_NEWNS).context("Unable to create mount namespace")?;
+
+    // mounts of the session must not propagate back to the host, but the session should still
+    // receive new mounts of the host (e.g. removable media)
+    mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_REC | MsFlags::MS_SLAVE,
+        None::<&str>,
+    )
+    .context("Unable to change mount propagation of /")?;
+
+    let mut options = "mode=1777".to_string();
+    if !settings.guest_private_tmp_size.is_empty() {
+        options.push_str(&format!(",size={}", settings.guest_private_tmp_size));
+    }
+
+    for path in &settings.guest_private_tmp_paths {
+        if !std::path::Path::new(path).is_dir() {
+            log::warn!("{path} is no directory, skipping private mount");
+            continue;
+        }
+        mount(
+            Some("tmpfs"),
+            path.as_str(),
+            Some("tmpfs"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            Some(options.as_str()),
+        )
+        .with_context(|| format!("Unable to mount private tmpfs on {path}"))?;
+        log::debug!("Mounted private tmpfs on {path}");
+    }
+
+    Ok(())
+}